@@ -0,0 +1,338 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use pipewire::spa::utils::Fraction;
+
+use crate::{
+    crop::CropRegion,
+    dump::DumpTarget,
+    frame::Chroma,
+    latency::LatencySpec,
+    limit,
+    mirror::MirrorTarget,
+    names::OnConflict,
+    pacing,
+    pause::PauseMode,
+    pip::PipLayout,
+    queue::DropPolicy,
+    remote::CommandKind,
+    sched::{CpuList, RtPolicy},
+    tally::TallySaving,
+    transform::{Rect, Rotation, Size},
+};
+
+/// Where frames come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CaptureSource {
+    /// A monitor or window picked through the screencast portal.
+    Portal,
+    /// Generated color bars, for development without a Wayland session.
+    Test,
+}
+
+/// Shares a Wayland screen using NDI.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Burn the wall clock at capture time into a corner of every frame.
+    #[arg(long)]
+    pub burn_in_clock: bool,
+
+    /// Also burn in the frame timecode below the clock.
+    #[arg(long, requires = "burn_in_clock")]
+    pub burn_in_timecode: bool,
+
+    /// Draw capture/send frame rates and pipeline latency onto the outgoing frames.
+    #[arg(long)]
+    pub debug_overlay: bool,
+
+    /// What to send while paused (toggled with SIGUSR1).
+    #[arg(long, value_enum, default_value_t = PauseMode::Freeze)]
+    pub pause_mode: PauseMode,
+
+    /// PNG image sent while paused with `--pause-mode slate`.
+    #[arg(long, value_name = "PATH", required_if_eq("pause_mode", "slate"))]
+    pub slate: Option<PathBuf>,
+
+    /// Send at most this many frames per second on the main source.
+    ///
+    /// Crops keep their own limit, given as `@FPS`.
+    #[arg(long, value_name = "FPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_fps: Option<u32>,
+
+    /// Send exactly this many frames per second on the main source, repeating the last frame
+    /// while the compositor delivers nothing new. Takes fractions and NTSC-family rates, e.g.
+    /// `30000/1001` or `59.94`.
+    #[arg(
+        long,
+        value_name = "FPS",
+        value_parser = pacing::parse_rate,
+        conflicts_with = "max_fps"
+    )]
+    pub cfr: Option<Fraction>,
+
+    /// Chroma resolution of the main source. 4:2:2 halves the data handed to NDI, but makes
+    /// small colored text visibly blurry.
+    #[arg(long, value_enum, default_value_t = Chroma::Rgb444)]
+    pub chroma: Chroma,
+
+    /// Draw a second shared stream as an inset over the main one, written as
+    /// `CORNER[,PERCENT[,MARGIN]]`, e.g. `bottom-right,25,16`.
+    ///
+    /// Two sources have to be shared in the portal dialog.
+    #[arg(long, value_name = "LAYOUT")]
+    pub pip: Option<PipLayout>,
+
+    /// Only send this region of the capture on the main source, written as `X,Y,WxH`.
+    #[arg(long, value_name = "X,Y,WxH")]
+    pub region: Option<Rect>,
+
+    /// Rotate the main source clockwise by this many degrees, e.g. for a monitor mounted
+    /// upright.
+    #[arg(long, value_enum, default_value_t = Rotation::None)]
+    pub rotate: Rotation,
+
+    /// Fit the main source on a frame of this size, adding black bars as needed.
+    #[arg(long, value_name = "WxH")]
+    pub canvas: Option<Size>,
+
+    /// Send a 1080x1920 portrait frame, same as `--canvas 1080x1920`.
+    #[arg(long, conflicts_with = "canvas")]
+    pub portrait: bool,
+
+    /// Also publish the main source under another name, written as
+    /// `NAME[@GROUPS][/N][:CHROMA]`.
+    ///
+    /// `GROUPS` puts it in these comma separated NDI groups, `N` divides its resolution and
+    /// `CHROMA` is its own `--chroma`, e.g. `--mirror "Desktop Proxy/2:422"`. Can be given
+    /// multiple times.
+    #[arg(long, value_name = "NAME[@GROUPS][/N][:CHROMA]")]
+    pub mirror: Vec<MirrorTarget>,
+
+    /// Lower the quality of sources while no receiver has them on program or preview.
+    #[arg(long, value_enum, default_value_t = TallySaving::Off)]
+    pub tally_saving: TallySaving,
+
+    /// Also publish a region of the capture as a separate source, named "Desktop - NAME".
+    ///
+    /// Can be given multiple times. `@FPS` limits its frame rate independently of the others,
+    /// and `:CHROMA` is its own `--chroma`.
+    #[arg(long, value_name = "NAME=X,Y,WxH[@FPS][:CHROMA]")]
+    pub crop: Vec<CropRegion>,
+
+    /// Also capture what is played on the default output device.
+    #[arg(long)]
+    pub audio: bool,
+
+    /// Do not capture the screen and only send audio, which implies `--audio`.
+    #[arg(long, conflicts_with_all = ["low_latency", "pip", "snapshot"])]
+    pub no_video: bool,
+
+    /// PNG image sent about once a second as the video of `--no-video`, for receivers that show
+    /// nothing without one.
+    #[arg(long, value_name = "PATH", requires = "no_video")]
+    pub cover: Option<PathBuf>,
+
+    /// Shift audio against video by this many milliseconds, positive values delaying it.
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0,
+        allow_negative_numbers = true
+    )]
+    pub audio_offset_ms: i64,
+
+    /// Amplify (or attenuate, when negative) captured audio by this many decibels.
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    pub audio_gain_db: f32,
+
+    /// Softly limit audio peaks instead of letting them clip.
+    #[arg(long)]
+    pub audio_limiter: bool,
+
+    /// Capture a headless virtual output created by the compositor instead of a monitor or
+    /// window, for machines without a display attached.
+    #[arg(long = "virtual")]
+    pub virtual_output: bool,
+
+    /// Write every Nth captured frame to DIR as PNG, with a JSON file describing its format.
+    #[arg(long, value_name = "DIR[:N]")]
+    pub dump_frames: Option<DumpTarget>,
+
+    /// Dump the untouched frame buffers instead of PNG images.
+    #[arg(long, requires = "dump_frames")]
+    pub dump_raw: bool,
+
+    /// Where to take frames from.
+    #[arg(long, value_enum, default_value_t = CaptureSource::Portal)]
+    pub source: CaptureSource,
+
+    /// Listen for JSON-RPC control commands on this Unix socket instead of
+    /// `$XDG_RUNTIME_DIR/ndi-screenshare.sock`.
+    #[arg(long, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Run the capture and send threads with this realtime priority (1-99), if permitted.
+    #[arg(long, value_name = "PRIORITY", value_parser = clap::value_parser!(i32).range(1..=99))]
+    pub rt_priority: Option<i32>,
+
+    /// Realtime scheduling policy used with `--rt-priority`.
+    #[arg(long, value_enum, default_value_t = RtPolicy::Rr, requires = "rt_priority")]
+    pub rt_policy: RtPolicy,
+
+    /// Pin the capture and send threads to these CPUs, e.g. `2,3` or `4-7`.
+    #[arg(long, value_name = "CPUS")]
+    pub cpu_affinity: Option<CpuList>,
+
+    /// Most memory frames waiting to be sent may use, in MiB, up to a TiB.
+    #[arg(
+        long,
+        value_name = "MIB",
+        default_value_t = 256,
+        value_parser = clap::value_parser!(u64).range(..=1 << 20)
+    )]
+    pub queue_budget_mb: u64,
+
+    /// What to do with new frames once the queue budget is used up.
+    #[arg(long, value_enum, default_value_t = DropPolicy::DropOldest)]
+    pub drop_policy: DropPolicy,
+
+    /// Start again with the command line and shared source of the previous run, without asking.
+    ///
+    /// Other arguments are ignored.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Ask the portal to let the shared source be shared again without asking, and save the
+    /// token for `--resume`.
+    ///
+    /// Until revoked in the desktop settings, anything able to read the session state can start
+    /// sharing the same source.
+    #[arg(long)]
+    pub persist: bool,
+
+    /// Stay idle until a control client or an NDI receiver connects, then start capturing.
+    ///
+    /// Meant for running as a resident user service.
+    #[arg(long)]
+    pub on_demand: bool,
+
+    /// Only capture what can be sent: no frame is copied from the compositor while the previous
+    /// one is still on its way out, and capture stops while no receiver is connected. Only works
+    /// with the portal source.
+    #[arg(long, conflicts_with_all = ["low_latency", "simulate_latency", "stitch"])]
+    pub throttle_capture: bool,
+
+    /// Capture, convert and send on a single thread, straight from the capture callback, for the
+    /// lowest latency at the cost of stalling capture while a frame is converted. Only works
+    /// with the portal source.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "audio",
+            "cfr",
+            "simulate_latency",
+            "pip",
+            "on_demand",
+            "exit_at_max_duration",
+        ]
+    )]
+    pub low_latency: bool,
+
+    /// Log every SPA param offered to and negotiated with the compositor, and the buffers it
+    /// hands out.
+    #[arg(long)]
+    pub debug_negotiation: bool,
+
+    /// Capture every shared monitor and send them as one wide frame, placed as they are laid out
+    /// on the desktop. Only works with the portal source.
+    #[arg(long, conflicts_with_all = ["pip", "low_latency", "virtual_output", "no_video"])]
+    pub stitch: bool,
+
+    /// Save the first captured frame as a PNG file and exit.
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Record how long each stage of the pipeline takes and write it to this file on exit, in
+    /// the Chrome trace format.
+    #[arg(long, value_name = "FILE")]
+    pub profile: Option<PathBuf>,
+
+    /// Once set up, forbid running programs and writing files anywhere but the state directory
+    /// and the directories of `--dump-frames` and `--snapshot`, using Landlock and seccomp.
+    #[arg(long)]
+    pub harden: bool,
+
+    /// Delay frames by MS milliseconds, plus up to JITTER more at random, before sending them.
+    ///
+    /// For testing how receivers and the staleness check cope with adverse conditions.
+    #[arg(long, value_name = "MS[:JITTER]")]
+    pub simulate_latency: Option<LatencySpec>,
+
+    /// Let the desktop blank or lock while streaming, instead of inhibiting idle through the
+    /// portal.
+    #[arg(long)]
+    pub no_idle_inhibit: bool,
+
+    /// Carry out these commands when receivers send them as NDI metadata, e.g.
+    /// `pause,resume,fps`. None are accepted by default.
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "COMMANDS")]
+    pub remote_commands: Vec<CommandKind>,
+
+    /// Stop sharing the desktop after this long, e.g. `90m` or `1h30m`, counted from the first
+    /// frame. A warning is drawn on the frames during the final minute.
+    #[arg(long, value_name = "DURATION", value_parser = limit::parse_duration)]
+    pub max_duration: Option<Duration>,
+
+    /// Exit once `--max-duration` is reached, instead of sending black until resumed.
+    #[arg(long, requires = "max_duration")]
+    pub exit_at_max_duration: bool,
+
+    /// What to do when another instance on this machine already publishes the sender name.
+    #[arg(long, value_enum, default_value_t = OnConflict::Suffix)]
+    pub on_conflict: OnConflict,
+
+    /// Tell receivers what is captured, e.g. the title of the window, and name the sender after
+    /// it from the next start on.
+    #[arg(long)]
+    pub name_from_source: bool,
+
+    /// Serve `/healthz` over HTTP on this address, e.g. `127.0.0.1:9180`.
+    #[arg(long, value_name = "ADDR")]
+    pub health_addr: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Things to do instead of sharing the screen.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the NDI sources visible on the network, and whether the ones of this machine are
+    /// among them.
+    ListSources {
+        /// How long to wait for sources to show up, in seconds.
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+
+        /// Only look in these comma-separated NDI groups.
+        #[arg(long)]
+        groups: Option<String>,
+    },
+    /// Show what an NDI source sends in a window.
+    #[cfg(feature = "view")]
+    View {
+        /// Name of the source, with or without the machine part, e.g. `Desktop` or
+        /// `HOST (Desktop)`.
+        source: String,
+
+        /// How long to look for the source, in seconds.
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+    },
+}
@@ -0,0 +1,276 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use crossbeam_channel::Sender;
+use pipewire as pw;
+use pipewire::{main_loop::MainLoop, spa};
+
+use crate::{
+    sink::FrameSink,
+    stats::{AudioLevels, MIN_LEVEL_DB},
+};
+
+/// A block of captured audio, deinterleaved into one plane per channel.
+pub struct AudioChunk {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub samples: Vec<f32>,
+    pub captured: SystemTime,
+}
+
+impl AudioChunk {
+    /// Number of samples per channel.
+    pub fn samples_per_channel(&self) -> u32 {
+        self.samples.len() as u32 / self.channels.max(1)
+    }
+}
+
+/// Level above which the limiter starts compressing, in linear full scale.
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+/// Adjusts levels and shifts audio against video before it is submitted.
+pub struct AudioOutput {
+    /// Added to the capture time of every chunk, may be negative.
+    offset_ms: i64,
+    /// Linear gain factor.
+    gain: f32,
+    limiter: bool,
+    meter: AudioMeter,
+}
+
+impl AudioOutput {
+    pub fn new(offset_ms: i64, gain_db: f32, limiter: bool) -> Self {
+        Self {
+            offset_ms,
+            gain: 10f32.powf(gain_db / 20.0),
+            limiter,
+            meter: AudioMeter::default(),
+        }
+    }
+
+    /// Levels of the audio sent so far, as receivers hear it.
+    pub fn levels(&self) -> AudioLevels {
+        self.meter.levels()
+    }
+
+    pub fn send(&mut self, sink: &mut impl FrameSink, mut chunk: AudioChunk) {
+        if self.gain != 1.0 || self.limiter {
+            for sample in &mut chunk.samples {
+                *sample *= self.gain;
+                if self.limiter {
+                    *sample = limit(*sample);
+                }
+            }
+        }
+
+        self.meter.measure(&chunk);
+        sink.send_audio(&ndi::AudioFrame {
+            sample_rate: chunk.sample_rate,
+            channels: chunk.channels,
+            samples: chunk.samples_per_channel(),
+            timecode: Some(timecode(chunk.captured) + self.offset_ms * 10_000),
+            data: &chunk.samples,
+        });
+    }
+}
+
+/// How fast a held peak falls back, like a peak programme meter.
+const PEAK_FALL_DB_PER_SEC: f32 = 12.0;
+/// Integration time of the RMS level.
+const RMS_WINDOW: Duration = Duration::from_millis(300);
+
+/// Follows the peak and RMS level of each channel with meter ballistics, so that readings are
+/// steady whatever the chunk size.
+#[derive(Default)]
+struct AudioMeter {
+    /// Linear held peak per channel.
+    peaks: Vec<f32>,
+    /// Running mean square per channel.
+    mean_squares: Vec<f32>,
+}
+
+impl AudioMeter {
+    fn measure(&mut self, chunk: &AudioChunk) {
+        let channels = chunk.channels.max(1) as usize;
+        let frames = chunk.samples_per_channel() as usize;
+        if frames == 0 || chunk.sample_rate == 0 {
+            return;
+        }
+        if self.peaks.len() != channels {
+            self.peaks = vec![0.0; channels];
+            self.mean_squares = vec![0.0; channels];
+        }
+
+        let duration = frames as f32 / chunk.sample_rate as f32;
+        let fall = 10f32.powf(-PEAK_FALL_DB_PER_SEC * duration / 20.0);
+        let weight = 1.0 - (-duration / RMS_WINDOW.as_secs_f32()).exp();
+
+        for (channel, plane) in chunk.samples.chunks_exact(frames).enumerate() {
+            let peak = plane.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let mean_square = plane.iter().map(|s| s * s).sum::<f32>() / frames as f32;
+            self.peaks[channel] = peak.max(self.peaks[channel] * fall);
+            self.mean_squares[channel] += (mean_square - self.mean_squares[channel]) * weight;
+        }
+    }
+
+    fn levels(&self) -> AudioLevels {
+        AudioLevels {
+            peak_db: self.peaks.iter().map(|&peak| to_db(peak)).collect(),
+            rms_db: self
+                .mean_squares
+                .iter()
+                .map(|ms| to_db(ms.sqrt()))
+                .collect(),
+        }
+    }
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(MIN_LEVEL_DB)
+}
+
+/// Soft limiter: passes quiet samples through and bends louder ones smoothly towards full
+/// scale, so that they never clip.
+fn limit(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= LIMITER_THRESHOLD {
+        return sample;
+    }
+
+    let headroom = 1.0 - LIMITER_THRESHOLD;
+    let limited = LIMITER_THRESHOLD + headroom * ((level - LIMITER_THRESHOLD) / headroom).tanh();
+    limited.copysign(sample)
+}
+
+/// Converts a wall clock time to NDI timecode, which the SDK synthesizes for video frames the
+/// same way: 100 ns units since the Unix epoch.
+fn timecode(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    (since_epoch.as_nanos() / 100) as i64
+}
+
+struct UserData {
+    format: spa::param::audio::AudioInfoRaw,
+}
+
+/// Captures what is played on the default output device.
+pub fn audio_loop(tx: Sender<AudioChunk>) -> Result<()> {
+    let main_loop = MainLoop::new(None)?;
+    let ctx = pw::context::Context::new(&main_loop)?;
+    let core = ctx.connect(None)?;
+
+    let data = UserData {
+        format: Default::default(),
+    };
+
+    let stream = pw::stream::Stream::new(
+        &core,
+        "audio-capture",
+        pw::properties::properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Music",
+            *pw::keys::STREAM_CAPTURE_SINK => "true",
+        },
+    )?;
+
+    let _listener = stream
+        .add_local_listener_with_user_data(data)
+        .param_changed(|_, user_data, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != pw::spa::param::ParamType::Format.as_raw() {
+                return;
+            }
+
+            let (media_type, media_subtype) =
+                match pw::spa::param::format_utils::parse_format(param) {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+            if media_type != pw::spa::param::format::MediaType::Audio
+                || media_subtype != pw::spa::param::format::MediaSubtype::Raw
+            {
+                return;
+            }
+
+            user_data
+                .format
+                .parse(param)
+                .expect("Failed to parse param changed to AudioInfoRaw");
+
+            println!(
+                "got audio format: {} Hz, {} channels",
+                user_data.format.rate(),
+                user_data.format.channels()
+            );
+        })
+        .process(move |stream, user_data| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let datas = buffer.datas_mut();
+            if datas.is_empty() {
+                return;
+            }
+
+            let size = datas[0].chunk().size() as usize;
+            let Some(data) = datas[0].data() else {
+                return;
+            };
+            let channels = user_data.format.channels().max(1) as usize;
+            let interleaved = &data[..size.min(data.len())];
+            let frames = interleaved.len() / (channels * 4);
+            if frames == 0 {
+                return;
+            }
+
+            let mut samples = vec![0.0; frames * channels];
+            for (i, sample) in interleaved
+                .chunks_exact(4)
+                .take(frames * channels)
+                .enumerate()
+            {
+                let value = f32::from_le_bytes(sample.try_into().unwrap());
+                samples[(i % channels) * frames + i / channels] = value;
+            }
+
+            tx.send(AudioChunk {
+                sample_rate: user_data.format.rate(),
+                channels: channels as u32,
+                samples,
+                captured: SystemTime::now(),
+            })
+            .ok();
+        })
+        .register()?;
+
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    let obj = pw::spa::pod::Object {
+        type_: pw::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: pw::spa::param::ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    let values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner();
+
+    let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    main_loop.run();
+
+    Ok(())
+}
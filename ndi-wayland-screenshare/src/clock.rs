@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pipewire::spa::utils::Fraction;
+
+use crate::overlay::{label_size, text_scale, Canvas, TEXT_BACKGROUND, TEXT_COLOR};
+
+/// Broken-down local time of day.
+pub struct LocalTime {
+    /// Days since Sunday.
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+}
+
+impl LocalTime {
+    pub fn at(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs() as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&secs, &mut tm) };
+
+        Self {
+            weekday: tm.tm_wday as u32,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+            second: tm.tm_sec as u32,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Burns the wall clock at capture time, and optionally the frame timecode, into frames.
+pub struct ClockBurnIn {
+    timecode: bool,
+}
+
+impl ClockBurnIn {
+    pub fn new(timecode: bool) -> Self {
+        Self { timecode }
+    }
+
+    /// Draws the clock, with the timecode counted at `frame_rate`, the rate frames leave with.
+    pub fn draw(&self, canvas: &mut Canvas, captured: Instant, frame_rate: Fraction) {
+        let time = LocalTime::at(SystemTime::now() - captured.elapsed());
+        let mut text = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            time.hour, time.minute, time.second, time.millis
+        );
+
+        if self.timecode {
+            let since_midnight = Duration::from_secs(
+                time.hour as u64 * 3600 + time.minute as u64 * 60 + time.second as u64,
+            ) + Duration::from_millis(time.millis as u64);
+            text.push_str(&format!("\nTC {}", timecode(since_midnight, frame_rate)));
+        }
+
+        let scale = text_scale(canvas.height());
+        let (width, height) = label_size(scale, &text);
+        let margin = 8 * scale;
+        canvas.label(
+            (canvas.width() - width.min(canvas.width())) as i32 - margin as i32,
+            (canvas.height() - height.min(canvas.height())) as i32 - margin as i32,
+            scale,
+            &text,
+            TEXT_COLOR,
+            TEXT_BACKGROUND,
+        );
+    }
+}
+
+/// SMPTE timecode of the frame a schedule at `rate` starting at midnight is on after
+/// `since_midnight`.
+///
+/// NTSC rates of 29.97 and 59.94 use drop-frame timecode, written with `;` before the frames,
+/// so that it keeps up with the clock.
+fn timecode(since_midnight: Duration, rate: Fraction) -> String {
+    if rate.num == 0 || rate.denom == 0 {
+        return "--:--:--:--".to_string();
+    }
+    let mut frames = (since_midnight.as_nanos() * rate.num as u128
+        / (rate.denom as u128 * 1_000_000_000)) as u64;
+    // Frames are labelled at the whole rate, e.g. 30 for 30000/1001.
+    let fps = ((rate.num as u64 + rate.denom as u64 / 2) / rate.denom as u64).max(1);
+
+    let drop_frame = rate.denom == 1001 && fps % 30 == 0;
+    if drop_frame {
+        // Labels 0 and 1 (0 to 3 at 59.94) are skipped at every minute but each tenth.
+        let dropped = fps / 15;
+        let per_minute = fps * 60 - dropped;
+        let per_ten_minutes = fps * 600 - dropped * 9;
+        let (tens, rest) = (frames / per_ten_minutes, frames % per_ten_minutes);
+        frames += dropped * 9 * tens;
+        if rest > dropped {
+            frames += dropped * ((rest - dropped) / per_minute);
+        }
+    }
+
+    let seconds = frames / fps;
+    format!(
+        "{:02}:{:02}:{:02}{}{:02}",
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        if drop_frame { ';' } else { ':' },
+        frames % fps
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC: Fraction = Fraction {
+        num: 30000,
+        denom: 1001,
+    };
+
+    #[test]
+    fn counts_whole_rates() {
+        let rate = Fraction { num: 25, denom: 1 };
+        assert_eq!(timecode(Duration::from_millis(1500), rate), "00:00:01:12");
+        assert_eq!(timecode(Duration::from_secs(3723), rate), "01:02:03:00");
+    }
+
+    #[test]
+    fn drops_frames_at_ntsc_rates() {
+        let at_frame = |frame: u64| Duration::from_nanos(frame * 1001 * 1_000_000_000 / 30000);
+        assert_eq!(timecode(at_frame(1799), NTSC), "00:00:59;29");
+        // Labels 00 and 01 do not exist at the start of a minute.
+        assert_eq!(timecode(at_frame(1800), NTSC), "00:01:00;02");
+        // But they do every ten minutes, where the timecode meets the clock again.
+        assert_eq!(timecode(Duration::from_secs(600), NTSC), "00:10:00;00");
+        assert_eq!(timecode(Duration::from_secs(3600), NTSC), "01:00:00;00");
+    }
+}
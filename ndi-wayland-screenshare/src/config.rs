@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{overlay::Color, schedule::ScheduleEntry};
+
+/// Settings read from `$XDG_CONFIG_HOME/ndi-wayland-screenshare/config.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub click_highlight: ClickHighlightConfig,
+    /// Times to stream during, all the time when empty.
+    pub schedule: Vec<ScheduleEntry>,
+    pub battery: BatteryConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickHighlightConfig {
+    pub enabled: bool,
+    pub color: Color,
+    pub duration_ms: u64,
+    pub radius: u32,
+}
+
+impl Default for ClickHighlightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: Color::rgba(255, 200, 0, 200),
+            duration_ms: 400,
+            radius: 28,
+        }
+    }
+}
+
+/// Reduced quality applied while running on battery.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryConfig {
+    pub enabled: bool,
+    pub max_fps: u32,
+    /// Both dimensions of the main source are divided by this.
+    pub scale: u32,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_fps: 15,
+            scale: 2,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, falling back to defaults when it does not exist.
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("ndi-wayland-screenshare").join("config.toml"))
+}
@@ -0,0 +1,142 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Result;
+use tokio::sync::{oneshot, Notify};
+
+use crate::args::CaptureSource;
+
+/// Runtime state that can be changed while streaming.
+pub struct Control {
+    source: CaptureSource,
+    paused: AtomicBool,
+    on_battery: AtomicBool,
+    /// Cleared while waiting for someone to need the share, see [`Control::set_idle`].
+    active: AtomicBool,
+    activation: Notify,
+    source_switch: Notify,
+    /// What the captured source calls itself, see `--name-from-source`.
+    source_label: Mutex<Option<String>>,
+    source_label_change: Notify,
+    snapshots: Mutex<Vec<SnapshotRequest>>,
+}
+
+/// Asks for the next captured frame to be written to `path` as PNG.
+pub struct SnapshotRequest {
+    pub path: PathBuf,
+    pub done: oneshot::Sender<Result<()>>,
+}
+
+impl Control {
+    pub fn new(source: CaptureSource) -> Self {
+        Self {
+            source,
+            paused: AtomicBool::new(false),
+            on_battery: AtomicBool::new(false),
+            active: AtomicBool::new(true),
+            activation: Notify::new(),
+            source_switch: Notify::new(),
+            source_label: Mutex::default(),
+            source_label_change: Notify::new(),
+            snapshots: Mutex::default(),
+        }
+    }
+
+    pub fn source(&self) -> CaptureSource {
+        self.source
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Whether the machine runs on battery, as far as it is known.
+    pub fn on_battery(&self) -> bool {
+        self.on_battery.load(Ordering::Relaxed)
+    }
+
+    pub fn set_on_battery(&self, on_battery: bool) {
+        self.on_battery.store(on_battery, Ordering::Relaxed);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Holds off capturing until [`Control::activate`] is called.
+    pub fn set_idle(&self) {
+        self.active.store(false, Ordering::Relaxed);
+    }
+
+    /// Starts capturing if the share was idle.
+    pub fn activate(&self) {
+        if !self.active.swap(true, Ordering::Relaxed) {
+            self.activation.notify_one();
+        }
+    }
+
+    pub async fn activated(&self) {
+        if !self.is_active() {
+            self.activation.notified().await;
+        }
+    }
+
+    /// Flips the paused state and returns the new one.
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Asks for the user to pick another source through the portal.
+    pub fn request_source_switch(&self) {
+        self.source_switch.notify_one();
+    }
+
+    pub async fn source_switch_requested(&self) {
+        self.source_switch.notified().await;
+    }
+
+    pub fn source_label(&self) -> Option<String> {
+        self.source_label.lock().unwrap().clone()
+    }
+
+    pub fn set_source_label(&self, label: String) {
+        let mut current = self.source_label.lock().unwrap();
+        if current.as_deref() != Some(label.as_str()) {
+            *current = Some(label);
+            self.source_label_change.notify_one();
+        }
+    }
+
+    /// Returns the label of the captured source once it changed.
+    pub async fn source_label_changed(&self) -> String {
+        loop {
+            self.source_label_change.notified().await;
+            if let Some(label) = self.source_label() {
+                return label;
+            }
+        }
+    }
+
+    /// Saves the next captured frame to `path`, returning once it is written.
+    pub async fn snapshot(&self, path: PathBuf) -> Result<()> {
+        let (done, result) = oneshot::channel();
+        self.snapshots
+            .lock()
+            .unwrap()
+            .push(SnapshotRequest { path, done });
+        result.await?
+    }
+
+    pub fn take_snapshot_requests(&self) -> Vec<SnapshotRequest> {
+        std::mem::take(&mut *self.snapshots.lock().unwrap())
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+use crate::{image::Image, sink::FrameSink};
+
+/// How often the cover is sent, only often enough for receivers to show it.
+const COVER_REFRESH: Duration = Duration::from_secs(1);
+
+/// Still image sent as the video of an audio-only source, see `--no-video`.
+pub struct Cover {
+    width: u32,
+    height: u32,
+    /// BGRx pixels.
+    data: Vec<u8>,
+    last_sent: Option<Instant>,
+}
+
+impl Cover {
+    pub fn new(image: &Image) -> Self {
+        Self {
+            width: image.width,
+            height: image.height,
+            data: image.render(image.width, image.height, true),
+            last_sent: None,
+        }
+    }
+
+    /// When the cover is sent next.
+    pub fn next_due(&self) -> Instant {
+        self.last_sent
+            .map_or_else(Instant::now, |sent| sent + COVER_REFRESH)
+    }
+
+    /// Sends the cover if it is due, returning whether it was.
+    pub fn poll(&mut self, sink: &mut impl FrameSink, now: Instant) -> bool {
+        if now < self.next_due() {
+            return false;
+        }
+        sink.send_video(ndi::Frame {
+            width: self.width,
+            height: self.height,
+            format: ndi::VideoFormat::BGRX,
+            data: &mut self.data[..],
+            stride_in_bytes: self.width * 4,
+            picture_aspect_ratio: None,
+            frame_rate: Some((1, COVER_REFRESH.as_secs() as u32)),
+        });
+        self.last_sent = Some(now);
+        true
+    }
+}
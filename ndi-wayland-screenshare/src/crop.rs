@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    frame::{downscale, ndi_format, ndi_rate, ndi_stride, Chroma, OwnedFrame},
+    pacing::FramePacer,
+    sink::{self, FrameSink},
+    tally::{Quality, TallyGate, TallySaving, REDUCED_SCALE},
+};
+
+/// A region of the captured frame published as its own NDI source, written as `NAME=X,Y,WxH`
+/// or `NAME=X,Y,WxH@FPS` to send it at a lower frame rate, followed by `:422` to send it as
+/// 4:2:2 YUV.
+#[derive(Debug, Clone)]
+pub struct CropRegion {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub max_fps: Option<u32>,
+    pub chroma: Chroma,
+}
+
+impl CropRegion {
+    /// Clips the region to a frame of the given size, returning `None` if nothing is left.
+    fn clip(&self, frame_width: u32, frame_height: u32) -> Option<(u32, u32, u32, u32)> {
+        if self.x >= frame_width || self.y >= frame_height {
+            return None;
+        }
+        let width = self.width.min(frame_width - self.x);
+        let height = self.height.min(frame_height - self.y);
+        (width > 0 && height > 0).then_some((self.x, self.y, width, height))
+    }
+}
+
+impl FromStr for CropRegion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Crop must be NAME=X,Y,WxH[@FPS][:CHROMA]: {}", s);
+        let (name, rect) = s.rsplit_once('=').ok_or_else(error)?;
+        let (rect, chroma) = Chroma::split_suffix(rect);
+        let (rect, max_fps) = match rect.split_once('@') {
+            Some((rect, fps)) => (rect, Some(fps)),
+            None => (rect, None),
+        };
+        let (x, rest) = rect.split_once(',').ok_or_else(error)?;
+        let (y, size) = rest.split_once(',').ok_or_else(error)?;
+        let (width, height) = size.split_once('x').ok_or_else(error)?;
+        if name.is_empty() {
+            return Err(error());
+        }
+
+        let number = |v: &str| v.trim().parse::<u32>().with_context(error);
+        Ok(Self {
+            name: name.to_string(),
+            x: number(x)?,
+            y: number(y)?,
+            width: number(width)?,
+            height: number(height)?,
+            max_fps: match max_fps {
+                Some(fps) => Some(number(fps).ok().filter(|&fps| fps > 0).ok_or_else(error)?),
+                None => None,
+            },
+            chroma: chroma.unwrap_or_default(),
+        })
+    }
+}
+
+/// Sends one crop of every frame through its own sender.
+pub struct CropOutput<S> {
+    /// Name of the NDI source, the main source's followed by the region's.
+    name: String,
+    region: CropRegion,
+    sender: S,
+    pacer: FramePacer,
+    tally: TallyGate,
+    /// Buffer handed back by the SDK, reused for the next crop.
+    spare: Option<Vec<u8>>,
+}
+
+impl<S: FrameSink> CropOutput<S> {
+    pub fn new(name: String, region: CropRegion, sender: S, tally_saving: TallySaving) -> Self {
+        Self {
+            name,
+            pacer: FramePacer::new(region.max_fps),
+            tally: TallyGate::new(tally_saving),
+            region,
+            sender,
+            spare: None,
+        }
+    }
+
+    pub fn send(&mut self, frame: &OwnedFrame) {
+        if !self.pacer.ready(frame.create_time) {
+            return;
+        }
+        let quality = self
+            .tally
+            .check(&self.name, self.sender.tally(), frame.create_time);
+        if quality == Quality::Skip {
+            return;
+        }
+
+        let size = frame.format.size();
+        let Some((x, y, width, height)) = self.region.clip(size.width, size.height) else {
+            return;
+        };
+
+        let stride = frame.stride as usize;
+        let row_len = width as usize * 4;
+        let mut data = self.spare.take().unwrap_or_default();
+        data.clear();
+        for row in y..y + height {
+            let start = row as usize * stride + x as usize * 4;
+            let Some(row) = frame.data.get(start..start + row_len) else {
+                return;
+            };
+            data.extend_from_slice(row);
+        }
+        let (data, width, height) = match quality {
+            Quality::Reduced => downscale(&data, width, height, row_len, REDUCED_SCALE),
+            _ => (data, width, height),
+        };
+        let (data, format, width) =
+            self.region
+                .chroma
+                .convert(data, width, height, frame.format.format());
+
+        self.spare = self.sender.send_video_async(ndi::Frame {
+            width,
+            height,
+            format: ndi_format(format),
+            data,
+            stride_in_bytes: ndi_stride(format, width),
+            picture_aspect_ratio: None,
+            frame_rate: ndi_rate(self.pacer.limit(frame.frame_rate())),
+        });
+    }
+
+    /// Creates the sender again if it stopped working.
+    pub fn recover(&mut self) {
+        sink::recover(&mut self.sender, &self.name);
+    }
+
+    #[cfg(test)]
+    pub fn sink(&self) -> &S {
+        &self.sender
+    }
+}
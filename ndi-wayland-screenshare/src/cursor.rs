@@ -0,0 +1,247 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use pipewire::spa::{self, param::video::VideoFormat};
+
+use crate::{config::ClickHighlightConfig, overlay::Canvas};
+
+/// Size to request for `SPA_META_Cursor`, enough for a 64x64 cursor bitmap.
+pub const CURSOR_META_SIZE: i32 = cursor_meta_size(64, 64);
+pub const CURSOR_META_MIN_SIZE: i32 = cursor_meta_size(1, 1);
+pub const CURSOR_META_MAX_SIZE: i32 = cursor_meta_size(256, 256);
+
+const fn cursor_meta_size(width: usize, height: usize) -> i32 {
+    (std::mem::size_of::<spa::sys::spa_meta_cursor>()
+        + std::mem::size_of::<spa::sys::spa_meta_bitmap>()
+        + width * height * 4) as i32
+}
+
+pub struct CursorBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub bgr: bool,
+    /// Tightly packed 32-bit pixels.
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct CursorState {
+    pub x: i32,
+    pub y: i32,
+    pub hotspot_x: i32,
+    pub hotspot_y: i32,
+    pub bitmap: Option<Arc<CursorBitmap>>,
+}
+
+/// Follows the cursor metadata attached to captured buffers.
+///
+/// Compositors only attach a bitmap when the cursor shape changes, so the last one is kept.
+#[derive(Default)]
+pub struct CursorTracker {
+    bitmap: Option<Arc<CursorBitmap>>,
+}
+
+impl CursorTracker {
+    /// Updates the tracked cursor from a buffer's metadata and returns its current state.
+    ///
+    /// # Safety
+    ///
+    /// `meta` must point to a `SPA_META_Cursor` region of `size` bytes.
+    pub unsafe fn update(
+        &mut self,
+        meta: *const spa::sys::spa_meta_cursor,
+        size: usize,
+    ) -> Option<CursorState> {
+        let cursor = &*meta;
+        // An id of 0 means the cursor is not visible in this frame.
+        if cursor.id == 0 {
+            return None;
+        }
+
+        // The offsets come from the compositor, nothing is read outside of the region.
+        let offset = cursor.bitmap_offset as usize;
+        if offset >= std::mem::size_of::<spa::sys::spa_meta_cursor>()
+            && offset + std::mem::size_of::<spa::sys::spa_meta_bitmap>() <= size
+        {
+            let bitmap = (meta as *const u8).add(offset) as *const spa::sys::spa_meta_bitmap;
+            if (*bitmap).format != 0 {
+                self.bitmap = read_bitmap(bitmap, size - offset).map(Arc::new);
+            }
+        }
+
+        Some(CursorState {
+            x: cursor.position.x,
+            y: cursor.position.y,
+            hotspot_x: cursor.hotspot.x,
+            hotspot_y: cursor.hotspot.y,
+            bitmap: self.bitmap.clone(),
+        })
+    }
+}
+
+/// Copies the pixels of a bitmap followed by `size` bytes of metadata, counted from its start.
+unsafe fn read_bitmap(
+    bitmap: *const spa::sys::spa_meta_bitmap,
+    size: usize,
+) -> Option<CursorBitmap> {
+    let base = bitmap as *const u8;
+    let bitmap = &*bitmap;
+    // An offset of 0 means the cursor is invisible.
+    if bitmap.offset == 0 || bitmap.size.width == 0 || bitmap.size.height == 0 {
+        return None;
+    }
+
+    let format = VideoFormat::from_raw(bitmap.format);
+    let (width, height) = (bitmap.size.width, bitmap.size.height);
+    let stride = bitmap.stride as usize;
+    let row_len = width as usize * 4;
+    let end = (height as usize - 1)
+        .checked_mul(stride)
+        .and_then(|rows| rows.checked_add(bitmap.offset as usize + row_len));
+    if end.map_or(true, |end| end > size) {
+        return None;
+    }
+
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+    for row in 0..height as usize {
+        let row =
+            std::slice::from_raw_parts(base.add(bitmap.offset as usize + row * stride), row_len);
+        pixels.extend_from_slice(row);
+    }
+
+    Some(CursorBitmap {
+        width,
+        height,
+        bgr: format == VideoFormat::BGRA || format == VideoFormat::BGRx,
+        pixels,
+    })
+}
+
+/// Watches evdev pointer devices for button presses.
+///
+/// Reading `/dev/input` usually requires the user to be in the `input` group.
+#[derive(Clone, Default)]
+pub struct ClickWatcher {
+    last_click: Arc<Mutex<Option<Instant>>>,
+}
+
+const EV_KEY: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_MIDDLE: u16 = 0x112;
+
+impl ClickWatcher {
+    pub fn spawn() -> Result<Self> {
+        let watcher = Self::default();
+        let mut watching = 0;
+
+        for entry in std::fs::read_dir("/dev/input")? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if !name.starts_with("event") || !has_left_button(name) {
+                continue;
+            }
+
+            match File::open(&path) {
+                Ok(device) => {
+                    let watcher = watcher.clone();
+                    std::thread::spawn(move || watcher.watch(device));
+                    watching += 1;
+                }
+                Err(e) => eprintln!("Cannot watch {} for clicks: {}", path.display(), e),
+            }
+        }
+
+        if watching == 0 {
+            return Err(anyhow!("No readable pointer devices found in /dev/input"));
+        }
+
+        Ok(watcher)
+    }
+
+    fn watch(&self, mut device: File) {
+        let mut event = [0u8; std::mem::size_of::<libc::input_event>()];
+        while device.read_exact(&mut event).is_ok() {
+            // The timestamp in front is two `long`s, so its size depends on the platform.
+            let event: libc::input_event =
+                unsafe { std::ptr::read_unaligned(event.as_ptr().cast()) };
+
+            if event.type_ == EV_KEY
+                && (BTN_LEFT..=BTN_MIDDLE).contains(&event.code)
+                && event.value == 1
+            {
+                *self.last_click.lock().unwrap() = Some(Instant::now());
+            }
+        }
+    }
+
+    pub fn last_click(&self) -> Option<Instant> {
+        *self.last_click.lock().unwrap()
+    }
+}
+
+/// Checks the key capability bitmap in sysfs, so that keyboards are never read.
+fn has_left_button(event_name: &str) -> bool {
+    let path = Path::new("/sys/class/input")
+        .join(event_name)
+        .join("device/capabilities/key");
+    let Ok(caps) = std::fs::read_to_string(path) else {
+        return false;
+    };
+
+    // Space separated words of `long` size, most significant first.
+    let words: Vec<&str> = caps.split_whitespace().rev().collect();
+    let bits = usize::BITS as usize;
+    let word = BTN_LEFT as usize / bits;
+    words
+        .get(word)
+        .and_then(|w| usize::from_str_radix(w, 16).ok())
+        .is_some_and(|w| w & (1 << (BTN_LEFT as usize % bits)) != 0)
+}
+
+/// Draws the cursor (captured as metadata) and a fading ring after every click.
+pub struct ClickHighlight {
+    config: ClickHighlightConfig,
+    watcher: ClickWatcher,
+}
+
+impl ClickHighlight {
+    pub fn new(config: ClickHighlightConfig, watcher: ClickWatcher) -> Self {
+        Self { config, watcher }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, cursor: &CursorState) {
+        if let Some(bitmap) = &cursor.bitmap {
+            canvas.blit(
+                cursor.x - cursor.hotspot_x,
+                cursor.y - cursor.hotspot_y,
+                bitmap.width,
+                bitmap.height,
+                &bitmap.pixels,
+                bitmap.bgr,
+            );
+        }
+
+        let Some(clicked) = self.watcher.last_click() else {
+            return;
+        };
+        let duration = Duration::from_millis(self.config.duration_ms);
+        let elapsed = clicked.elapsed();
+        if elapsed >= duration {
+            return;
+        }
+
+        // The ring expands while fading out.
+        let progress = elapsed.as_secs_f32() / duration.as_secs_f32();
+        let outer = (self.config.radius as f32 * (0.5 + 0.5 * progress)) as u32;
+        let color = self.config.color.faded(1.0 - progress);
+        canvas.ring(cursor.x, cursor.y, outer, outer.saturating_sub(4), color);
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ndi::NdiLib;
+
+use crate::names;
+
+/// Prints every NDI source discovery finds within `wait`, marking those of this machine, and
+/// hints at what blocks discovery if none of them show up while instances are running.
+pub fn list_sources(ndi: &NdiLib, wait: Duration, groups: Option<&str>) -> Result<()> {
+    let finder = ndi.create_finder(true, groups)?;
+    println!("Looking for NDI sources for {} s...", wait.as_secs());
+    let started = Instant::now();
+    while let Some(left) = wait.checked_sub(started.elapsed()) {
+        if !finder.wait_for_sources(left) {
+            break;
+        }
+    }
+
+    let mut sources = finder.current_sources();
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+    let host = names::hostname();
+    let is_local = |machine: &str| {
+        host.as_deref()
+            .is_some_and(|host| machine.eq_ignore_ascii_case(host))
+    };
+
+    let mut local = 0;
+    for source in &sources {
+        let here = source
+            .machine_and_name()
+            .is_some_and(|(machine, _)| is_local(machine));
+        local += here as usize;
+        match (&source.url_address, here) {
+            (Some(address), true) => println!("{}  {}  (this machine)", source.name, address),
+            (Some(address), false) => println!("{}  {}", source.name, address),
+            (None, true) => println!("{}  (this machine)", source.name),
+            (None, false) => println!("{}", source.name),
+        }
+    }
+    println!(
+        "{} source(s) found, {} of them on this machine",
+        sources.len(),
+        local
+    );
+
+    let running = names::running_instances();
+    if local == 0 && running > 0 {
+        println!(
+            "{} instance(s) run on this machine but none is discoverable. \
+             Check that mDNS (UDP 5353) and NDI (TCP 5959 and up) are allowed by the firewall.",
+            running
+        );
+    } else if sources.is_empty() {
+        println!(
+            "Nothing was found. Check that mDNS (UDP 5353) is allowed by the firewall, \
+             and that the other machines are in the same NDI groups."
+        );
+    }
+    Ok(())
+}
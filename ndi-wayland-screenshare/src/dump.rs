@@ -0,0 +1,150 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::Sender;
+use pipewire::spa::param::video::VideoInfoRaw;
+
+use crate::{frame::OwnedFrame, image::Image};
+
+/// Where and how often to dump frames, written as `DIR[:N]`.
+#[derive(Debug, Clone)]
+pub struct DumpTarget {
+    pub dir: PathBuf,
+    /// Dump every Nth captured frame.
+    pub every: u32,
+}
+
+impl FromStr for DumpTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (dir, every) = match s.rsplit_once(':') {
+            Some((dir, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                (dir, n.parse()?)
+            }
+            _ => (s, 1),
+        };
+        if dir.is_empty() || every == 0 {
+            return Err(anyhow!("Dump target must be DIR[:N] with N > 0: {}", s));
+        }
+
+        Ok(Self {
+            dir: dir.into(),
+            every,
+        })
+    }
+}
+
+struct DumpJob {
+    index: u64,
+    format: VideoInfoRaw,
+    stride: u32,
+    captured: SystemTime,
+    data: Vec<u8>,
+}
+
+/// Writes captured frames to disk on a background thread, for attaching to bug reports.
+///
+/// Every dumped frame gets a PNG (or the untouched buffer with `raw`) and a JSON file
+/// describing its format.
+pub struct FrameDumper {
+    every: u32,
+    count: u64,
+    tx: Sender<DumpJob>,
+}
+
+impl FrameDumper {
+    pub fn spawn(target: DumpTarget, raw: bool) -> Result<Self> {
+        std::fs::create_dir_all(&target.dir)
+            .with_context(|| format!("Failed to create {}", target.dir.display()))?;
+
+        // Frames are skipped rather than queued without bounds if the disk cannot keep up.
+        let (tx, rx) = crossbeam_channel::bounded::<DumpJob>(4);
+        let dir = target.dir;
+        std::thread::spawn(move || {
+            for job in rx {
+                if let Err(e) = write_job(&dir, &job, raw) {
+                    eprintln!("Failed to dump frame {}: {}", job.index, e);
+                }
+            }
+        });
+
+        Ok(Self {
+            every: target.every,
+            count: 0,
+            tx,
+        })
+    }
+
+    pub fn capture(&mut self, frame: &OwnedFrame) {
+        let index = self.count;
+        self.count += 1;
+        if index % self.every as u64 != 0 {
+            return;
+        }
+
+        let job = DumpJob {
+            index,
+            format: frame.format,
+            stride: frame.stride,
+            captured: SystemTime::now() - frame.create_time.elapsed(),
+            data: frame.data.clone(),
+        };
+        if self.tx.try_send(job).is_err() {
+            println!("Frame dump falling behind, skipping frame {}", index);
+        }
+    }
+}
+
+fn write_job(dir: &Path, job: &DumpJob, raw: bool) -> Result<()> {
+    let size = job.format.size();
+    let name = format!("frame-{:06}", job.index);
+
+    let file_name = if raw {
+        let file_name = format!("{}.raw", name);
+        std::fs::write(dir.join(&file_name), &job.data)?;
+        file_name
+    } else {
+        let file_name = format!("{}.png", name);
+        Image::from_frame(job.format, job.stride, &job.data).write_png(&dir.join(&file_name))?;
+        file_name
+    };
+
+    let captured_ms = job
+        .captured
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let sidecar = format!(
+        concat!(
+            "{{\n",
+            "  \"file\": \"{}\",\n",
+            "  \"index\": {},\n",
+            "  \"format\": \"{:?}\",\n",
+            "  \"width\": {},\n",
+            "  \"height\": {},\n",
+            "  \"stride\": {},\n",
+            "  \"buffer_size\": {},\n",
+            "  \"framerate\": \"{}/{}\",\n",
+            "  \"captured_unix_ms\": {}\n",
+            "}}\n"
+        ),
+        file_name,
+        job.index,
+        job.format.format(),
+        size.width,
+        size.height,
+        job.stride,
+        job.data.len(),
+        job.format.framerate().num,
+        job.format.framerate().denom,
+        captured_ms
+    );
+    std::fs::write(dir.join(format!("{}.json", name)), sidecar)?;
+
+    Ok(())
+}
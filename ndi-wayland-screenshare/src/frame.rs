@@ -0,0 +1,198 @@
+use std::time::Instant;
+
+use clap::ValueEnum;
+use pipewire::spa::{self, param::video::VideoFormat, utils::Fraction};
+
+use crate::{cursor::CursorState, queue::QueueTicket};
+
+pub struct OwnedFrame {
+    pub format: spa::param::video::VideoInfoRaw,
+    pub create_time: Instant,
+    pub data: Vec<u8>,
+    /// Bytes per row. Captured frames are packed to 4 bytes per pixel, without row padding.
+    pub stride: u32,
+    /// Only present when the cursor is captured as metadata instead of being embedded.
+    pub cursor: Option<CursorState>,
+    /// Set while the frame counts towards the queue memory budget.
+    pub queued: Option<QueueTicket>,
+    /// Width over height as the source is displayed, `None` for square pixels.
+    pub display_aspect: Option<f32>,
+}
+
+impl OwnedFrame {
+    /// The nominal frame rate, falling back to the maximum rate for variable rate streams.
+    pub fn frame_rate(&self) -> Fraction {
+        let rate = self.format.framerate();
+        if rate.num > 0 && rate.denom > 0 {
+            rate
+        } else {
+            self.format.max_framerate()
+        }
+    }
+}
+
+/// Aspect ratio to display a frame of `width` by `height` pixels with, given the logical size of
+/// its source.
+///
+/// Compositors scale buffers uniformly under HiDPI and fractional scaling, so this is only
+/// `Some` when the scaled size was rounded or stretched enough to distort the picture.
+pub fn display_aspect(width: u32, height: u32, logical_size: Option<(u32, u32)>) -> Option<f32> {
+    let (logical_width, logical_height) = logical_size?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let pixels = width as f32 / height as f32;
+    let logical = logical_width as f32 / logical_height as f32;
+    ((pixels / logical - 1.0).abs() > 0.01).then_some(logical)
+}
+
+/// Frame rate as NDI takes it, `None` when unknown.
+pub fn ndi_rate(rate: Fraction) -> Option<(u32, u32)> {
+    (rate.num > 0 && rate.denom > 0).then_some((rate.num, rate.denom))
+}
+
+/// Maps a negotiated PipeWire format to the matching NDI FourCC.
+pub fn ndi_format(format: VideoFormat) -> ndi::VideoFormat {
+    match format {
+        VideoFormat::RGBA => ndi::VideoFormat::RGBA,
+        VideoFormat::RGBx => ndi::VideoFormat::RGBX,
+        VideoFormat::BGRA => ndi::VideoFormat::BGRA,
+        VideoFormat::UYVY => ndi::VideoFormat::UYVY,
+        _ => ndi::VideoFormat::BGRX,
+    }
+}
+
+/// Bytes per row of a tightly packed frame `width` pixels wide.
+pub fn ndi_stride(format: VideoFormat, width: u32) -> u32 {
+    match format {
+        VideoFormat::UYVY => width * 2,
+        _ => width * 4,
+    }
+}
+
+/// Chroma resolution of a source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Chroma {
+    /// 4:2:2 YUV, half the data but blurry colored text.
+    #[value(name = "422")]
+    Yuv422,
+    /// Full resolution RGB, as captured.
+    #[default]
+    #[value(name = "444")]
+    Rgb444,
+}
+
+impl Chroma {
+    /// Splits a `:422` or `:444` suffix off a source given on the command line.
+    pub fn split_suffix(s: &str) -> (&str, Option<Self>) {
+        s.rsplit_once(':')
+            .and_then(|(rest, chroma)| Some((rest, Self::from_str(chroma, false).ok()?)))
+            .map_or((s, None), |(rest, chroma)| (rest, Some(chroma)))
+    }
+
+    /// Converts a tightly packed 32-bit frame to this chroma resolution, returning the data to
+    /// send along with its format and width.
+    pub fn convert(
+        self,
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: VideoFormat,
+    ) -> (Vec<u8>, VideoFormat, u32) {
+        if self == Chroma::Yuv422 && width >= 2 {
+            let uyvy = to_uyvy(&data, width, height, width as usize * 4, format);
+            (uyvy, VideoFormat::UYVY, width & !1)
+        } else {
+            (data, format, width)
+        }
+    }
+}
+
+/// Converts a packed 32-bit RGB image to UYVY, dropping the last column of odd widths.
+///
+/// Uses limited range BT.709, or BT.601 below 720 lines, as NDI receivers expect.
+pub fn to_uyvy(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: VideoFormat,
+) -> Vec<u8> {
+    let bgr = matches!(format, VideoFormat::BGRx | VideoFormat::BGRA);
+    // Coefficients scaled by 256 for Y, U and V, each from R, G and B.
+    let coefficients: [[i32; 3]; 3] = if height >= 720 {
+        [[47, 157, 16], [-26, -86, 112], [112, -102, -10]]
+    } else {
+        [[66, 129, 25], [-38, -74, 112], [112, -94, -18]]
+    };
+    let convert = |[r, g, b]: [i32; 3], row: usize, offset: i32| {
+        let [cr, cg, cb] = coefficients[row];
+        ((cr * r + cg * g + cb * b + 128) >> 8) + offset
+    };
+
+    let pairs = width as usize / 2;
+    let mut out = Vec::with_capacity(pairs * 4 * height as usize);
+    for row in data.chunks(stride).take(height as usize) {
+        for pair in row.chunks_exact(8).take(pairs) {
+            let rgb = |pixel: &[u8]| {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(i32::from);
+                if bgr {
+                    [b, g, r]
+                } else {
+                    [r, g, b]
+                }
+            };
+            let (left, right) = (rgb(&pair[..4]), rgb(&pair[4..]));
+            // Both pixels share the average color.
+            let mean = [0, 1, 2].map(|i| (left[i] + right[i] + 1) / 2);
+            out.extend(
+                [
+                    convert(mean, 1, 128),
+                    convert(left, 0, 16),
+                    convert(mean, 2, 128),
+                    convert(right, 0, 16),
+                ]
+                .map(|v| v.clamp(0, 255) as u8),
+            );
+        }
+    }
+    out
+}
+
+/// Shrinks a packed 32-bit image by `factor` in both dimensions, averaging each block of
+/// `factor` by `factor` pixels.
+pub fn downscale(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    factor: u32,
+) -> (Vec<u8>, u32, u32) {
+    let factor = factor.max(1);
+    let (out_width, out_height) = ((width / factor).max(1), (height / factor).max(1));
+    let mut out = Vec::with_capacity(out_width as usize * out_height as usize * 4);
+
+    for y in 0..out_height {
+        let rows = y * factor..((y + 1) * factor).min(height);
+        for x in 0..out_width {
+            let columns = x * factor..((x + 1) * factor).min(width);
+            let mut sums = [0u32; 4];
+            for row in rows.clone() {
+                for column in columns.clone() {
+                    let offset = row as usize * stride + column as usize * 4;
+                    if let Some(pixel) = data.get(offset..offset + 4) {
+                        for (sum, &value) in sums.iter_mut().zip(pixel) {
+                            *sum += value as u32;
+                        }
+                    }
+                }
+            }
+
+            let count = rows.len() as u32 * columns.len() as u32;
+            out.extend(sums.map(|sum| (sum / count.max(1)) as u8));
+        }
+    }
+
+    (out, out_width, out_height)
+}
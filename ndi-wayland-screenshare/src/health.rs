@@ -0,0 +1,198 @@
+use std::{fmt::Write, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{control::Control, stats::Stats};
+
+/// A share that has not sent a frame for this long, without being paused, is considered wedged.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request head that is read, anything after it is ignored.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Serves a minimal HTTP endpoint for orchestration and uptime monitors.
+///
+/// `GET /healthz` answers 200 while frames are flowing (or the share is paused or idle on
+/// purpose) and 503 once the pipeline is stuck, with details in a JSON body either way.
+/// `GET /metrics` returns the pipeline counters in the Prometheus text format.
+pub async fn serve(addr: SocketAddr, control: Arc<Control>, stats: Arc<Stats>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Serving health checks on http://{}/healthz", addr);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (control, stats) = (control.clone(), stats.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, &control, &stats).await {
+                eprintln!("Health client error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_client(mut stream: TcpStream, control: &Control, stats: &Stats) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/healthz")) => healthz(control, stats),
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics(control, stats)),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        if body.starts_with('{') {
+            "application/json"
+        } else if body.starts_with('#') {
+            "text/plain; version=0.0.4"
+        } else {
+            "text/plain"
+        },
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+fn healthz(control: &Control, stats: &Stats) -> (&'static str, String) {
+    let paused = control.is_paused();
+    let idle = !control.is_active();
+    let off_air = stats.is_off_air();
+    let last_frame_age = stats.last_sent_age();
+    let streaming = !paused && last_frame_age.is_some_and(|age| age < STALL_TIMEOUT);
+
+    let body = json!({
+        "streaming": streaming,
+        "paused": paused,
+        "idle": idle,
+        "off_air": off_air,
+        "fps": stats.last_report().send_fps,
+        "last_frame_age_ms": last_frame_age.map(|age| age.as_millis() as u64),
+        "ndi_connections": stats.connections(),
+    });
+    let status = if streaming || paused || idle || off_air {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    };
+    (status, format!("{}\n", body))
+}
+
+fn metrics(control: &Control, stats: &Stats) -> String {
+    let totals = stats.snapshot();
+    let report = stats.last_report();
+    let metrics: [(&str, &str, &str, f64); 14] = [
+        (
+            "frames_captured_total",
+            "counter",
+            "Frames received from the compositor.",
+            totals.captured as f64,
+        ),
+        (
+            "frames_sent_total",
+            "counter",
+            "Frames handed to NDI.",
+            totals.sent as f64,
+        ),
+        (
+            "frames_dropped_total",
+            "counter",
+            "Frames dropped because the queue was full or they were stale.",
+            totals.dropped as f64,
+        ),
+        (
+            "frames_corrupted_total",
+            "counter",
+            "Buffers flagged as corrupted by PipeWire.",
+            totals.corrupted as f64,
+        ),
+        (
+            "frames_repeated_total",
+            "counter",
+            "Frames sent again to keep a constant frame rate.",
+            totals.repeated as f64,
+        ),
+        (
+            "sent_bytes_total",
+            "counter",
+            "Uncompressed frame data handed to NDI.",
+            totals.sent_bytes as f64,
+        ),
+        (
+            "queue_depth_frames",
+            "gauge",
+            "Frames waiting to be sent.",
+            totals.queue_depth as f64,
+        ),
+        (
+            "queue_high_watermark_frames",
+            "gauge",
+            "Most frames ever waiting to be sent at once.",
+            totals.queue_high_watermark as f64,
+        ),
+        (
+            "queue_bytes",
+            "gauge",
+            "Memory held by frames waiting to be sent.",
+            totals.queued_bytes as f64,
+        ),
+        (
+            "queue_full_seconds_total",
+            "counter",
+            "Time the queue spent at its memory budget.",
+            totals.queue_full_us as f64 / 1e6,
+        ),
+        (
+            "latency_seconds",
+            "gauge",
+            "Average time from capture to send over the last report.",
+            report.latency_ms / 1000.0,
+        ),
+        (
+            "ndi_connections",
+            "gauge",
+            "Receivers connected to the main source.",
+            stats.connections() as f64,
+        ),
+        (
+            "paused",
+            "gauge",
+            "Whether the share is paused.",
+            control.is_paused() as u8 as f64,
+        ),
+        (
+            "idle",
+            "gauge",
+            "Whether the share waits for a client before capturing.",
+            !control.is_active() as u8 as f64,
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in metrics {
+        let name = format!("ndi_screenshare_{}", name);
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        let _ = writeln!(body, "{} {}", name, value);
+    }
+    body
+}
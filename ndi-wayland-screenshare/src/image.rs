@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use pipewire::spa::param::video::{VideoFormat, VideoInfoRaw};
+
+/// An 8-bit straight-alpha RGBA image.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    pub fn load_png(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let bytes = &buf[..info.buffer_size()];
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => bytes.to_vec(),
+            png::ColorType::Rgb => bytes
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => bytes
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => bytes.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            png::ColorType::Indexed => return Err(anyhow!("Indexed PNG was not expanded")),
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+
+    /// Converts a packed 32-bit frame, honouring its stride.
+    pub fn from_frame(format: VideoInfoRaw, stride: u32, data: &[u8]) -> Self {
+        let size = format.size();
+        let format = format.format();
+        let bgr = format == VideoFormat::BGRx || format == VideoFormat::BGRA;
+        let alpha = format == VideoFormat::BGRA || format == VideoFormat::RGBA;
+
+        let mut rgba = vec![0; size.width as usize * size.height as usize * 4];
+        for (y, out) in rgba.chunks_exact_mut(size.width as usize * 4).enumerate() {
+            let start = y * stride as usize;
+            let Some(row) = data.get(start..start + out.len()) else {
+                break;
+            };
+            for (src, dst) in row.chunks_exact(4).zip(out.chunks_exact_mut(4)) {
+                let (r, b) = if bgr {
+                    (src[2], src[0])
+                } else {
+                    (src[0], src[2])
+                };
+                dst.copy_from_slice(&[r, src[1], b, if alpha { src[3] } else { 255 }]);
+            }
+        }
+
+        Self {
+            width: size.width,
+            height: size.height,
+            rgba,
+        }
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.rgba)?;
+        Ok(())
+    }
+
+    /// Renders the image into a packed 32-bit frame, scaled to fit and centered on black.
+    pub fn render(&self, width: u32, height: u32, bgr: bool) -> Vec<u8> {
+        let mut out = vec![0; width as usize * height as usize * 4];
+        if self.width == 0 || self.height == 0 {
+            return out;
+        }
+
+        let scale = (width as f64 / self.width as f64).min(height as f64 / self.height as f64);
+        let w = ((self.width as f64 * scale) as u32).clamp(1, width);
+        let h = ((self.height as f64 * scale) as u32).clamp(1, height);
+        let (x0, y0) = ((width - w) / 2, (height - h) / 2);
+
+        for y in 0..h {
+            let sy = (y as u64 * self.height as u64 / h as u64) as usize;
+            for x in 0..w {
+                let sx = (x as u64 * self.width as u64 / w as u64) as usize;
+                let src = (sy * self.width as usize + sx) * 4;
+                let dst = ((y0 + y) as usize * width as usize + (x0 + x) as usize) * 4;
+
+                // Blend over black.
+                let p = &self.rgba[src..src + 4];
+                let c = |v: u8| (v as u32 * p[3] as u32 / 255) as u8;
+                let (r, g, b) = (c(p[0]), c(p[1]), c(p[2]));
+                out[dst..dst + 4].copy_from_slice(&if bgr {
+                    [b, g, r, 255]
+                } else {
+                    [r, g, b, 255]
+                });
+            }
+        }
+
+        out
+    }
+}
@@ -0,0 +1,57 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use ashpd::{
+    desktop::{
+        inhibit::{InhibitFlags, InhibitProxy},
+        Request,
+    },
+    WindowIdentifier,
+};
+
+use crate::control::Control;
+
+/// How often the streaming state is checked, and a failed request retried.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps the desktop from blanking or locking while the share is running, through the Inhibit
+/// portal, and lets it go again while paused or idle.
+///
+/// It does not go by whether frames are flowing, as an unchanging screen sends none.
+pub async fn run(control: Arc<Control>) -> Result<()> {
+    let proxy = InhibitProxy::new().await?;
+    let mut inhibitor: Option<Request<()>> = None;
+
+    loop {
+        let streaming = control.is_active() && !control.is_paused();
+
+        match (streaming, &inhibitor) {
+            (true, None) => {
+                match proxy
+                    .inhibit(
+                        &WindowIdentifier::default(),
+                        InhibitFlags::Idle.into(),
+                        "Sharing the screen over NDI",
+                    )
+                    .await
+                {
+                    Ok(request) => {
+                        inhibitor = Some(request);
+                        println!("Inhibiting idle while streaming");
+                    }
+                    Err(e) => eprintln!("Cannot inhibit idle, trying again: {}", e),
+                }
+            }
+            (false, Some(request)) => {
+                if let Err(e) = request.close().await {
+                    eprintln!("Failed to stop inhibiting idle: {}", e);
+                }
+                inhibitor = None;
+                println!("No longer inhibiting idle");
+            }
+            _ => {}
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+use crate::{frame::OwnedFrame, pipeline::Pipeline};
+
+/// Sends frames right from the capture callback instead of handing them to the NDI thread, see
+/// `--low-latency`.
+///
+/// Everything the NDI thread does between frames happens in [`InlineSender::poll`], which the
+/// capture loop calls on a timer.
+pub struct InlineSender {
+    pipeline: Pipeline<ndi::Sender>,
+}
+
+impl InlineSender {
+    pub fn new(pipeline: Pipeline<ndi::Sender>) -> Self {
+        Self { pipeline }
+    }
+
+    /// Sends a frame as soon as it is captured, or discards it while paused.
+    pub fn send_frame(&mut self, frame: OwnedFrame) {
+        if self.poll() {
+            self.pipeline.discard(frame);
+        } else {
+            self.pipeline.send_frame(frame);
+        }
+    }
+
+    /// Recovers senders, publishes stats, follows commands and refreshes the pause screen,
+    /// returning whether sending is paused.
+    pub fn poll(&mut self) -> bool {
+        self.pipeline.poll_senders(Instant::now());
+        self.pipeline.poll_stats();
+        self.pipeline.poll_commands();
+        self.pipeline.poll_source_label();
+        // Exiting is ruled out together with this mode, the limit only pauses.
+        self.pipeline.poll_limit();
+        self.pipeline.poll_pause()
+    }
+}
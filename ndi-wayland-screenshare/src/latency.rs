@@ -0,0 +1,85 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+
+/// Artificial delay put on frames before they are sent, written as `MS` or `MS:JITTER`.
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySpec {
+    pub delay: Duration,
+    /// Largest random delay added on top of `delay`.
+    pub jitter: Duration,
+}
+
+impl FromStr for LatencySpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Latency must be MS[:JITTER]: {}", s);
+        let (delay, jitter) = match s.split_once(':') {
+            Some((delay, jitter)) => (delay, Some(jitter)),
+            None => (s, None),
+        };
+        let millis = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .with_context(error)
+        };
+
+        Ok(Self {
+            delay: millis(delay)?,
+            jitter: jitter.map(millis).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Holds frames back for a configurable, randomly varying time, to test how receivers and the
+/// staleness check cope with a late or uneven stream.
+#[derive(Debug, Clone)]
+pub struct LatencySimulator {
+    spec: LatencySpec,
+    /// State of the xorshift generator picking the jitter.
+    rng: u64,
+    last_due: Option<Instant>,
+}
+
+impl LatencySimulator {
+    pub fn new(spec: LatencySpec) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            spec,
+            // The generator gets stuck on zero.
+            rng: seed | 1,
+            last_due: None,
+        }
+    }
+
+    /// Returns when a frame arriving at `now` is to be sent.
+    ///
+    /// Frames keep their order, a frame never goes out before the one received ahead of it.
+    pub fn due(&mut self, now: Instant) -> Instant {
+        let jitter_ms = self.spec.jitter.as_millis() as u64;
+        let jitter = match jitter_ms {
+            0 => Duration::ZERO,
+            _ => Duration::from_millis(self.next_random() % (jitter_ms + 1)),
+        };
+
+        let due = now + self.spec.delay + jitter;
+        let due = self.last_due.map_or(due, |last| due.max(last));
+        self.last_due = Some(due);
+        due
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
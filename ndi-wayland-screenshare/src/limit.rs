@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    frame::OwnedFrame,
+    overlay::{label_size, text_scale, Canvas, Color, TEXT_COLOR},
+};
+
+/// Final stretch of the maximum duration during which a warning is drawn on frames.
+const WARNING_PERIOD: Duration = Duration::from_secs(60);
+const WARNING_BACKGROUND: Color = Color::rgba(200, 0, 0, 220);
+
+/// Parses a duration written as hours, minutes and seconds, e.g. `90m`, `1h30m` or `45s`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let error = || anyhow!("Duration must be like 2h, 90m, 1h30m or 45s: {}", s);
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(error()),
+        };
+        total = number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .and_then(|secs| secs.checked_add(total))
+            .ok_or_else(error)?;
+        number.clear();
+    }
+    if !number.is_empty() || total == 0 {
+        return Err(error());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Stops sharing after a set time, counted from the first frame, warning on the frames
+/// themselves during the final minute.
+pub struct DurationLimit {
+    max: Duration,
+    /// Whether to exit once the time is up, instead of only stopping to share the desktop.
+    pub exit: bool,
+    started: Option<Instant>,
+    expired: bool,
+}
+
+impl DurationLimit {
+    pub fn new(max: Duration, exit: bool) -> Self {
+        Self {
+            max,
+            exit,
+            started: None,
+            expired: false,
+        }
+    }
+
+    /// Returns whether the time is up, only the first time it is.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let Some(started) = self.started else {
+            return false;
+        };
+        if self.expired || now < started + self.max {
+            return false;
+        }
+        self.expired = true;
+        true
+    }
+
+    /// Starts counting, and draws the warning in the final minute.
+    pub fn draw(&mut self, frame: &mut OwnedFrame) {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        if self.expired {
+            return;
+        }
+        let left = self.max.saturating_sub(started.elapsed());
+        if left > WARNING_PERIOD {
+            return;
+        }
+
+        let size = frame.format.size();
+        let mut canvas = Canvas::new(
+            &mut frame.data,
+            size.width,
+            size.height,
+            frame.stride as usize,
+            frame.format.format(),
+        );
+        let text = format!("Sharing stops in {} s", left.as_secs() + 1);
+        let scale = text_scale(canvas.height()) * 2;
+        let (width, _) = label_size(scale, &text);
+        canvas.label(
+            (canvas.width().saturating_sub(width) / 2) as i32,
+            (8 * scale) as i32,
+            scale,
+            &text,
+            TEXT_COLOR,
+            WARNING_BACKGROUND,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(
+            parse_duration(" 1h30m ").unwrap(),
+            Duration::from_secs(5400)
+        );
+        assert_eq!(parse_duration("90m").unwrap(), Duration::from_secs(5400));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for s in ["", "0s", "10", "h", "5d", "1h30", "99999999999999999h"] {
+            assert!(parse_duration(s).is_err(), "{}", s);
+        }
+    }
+}
@@ -1,72 +1,454 @@
-use std::{os::fd::OwnedFd, thread::sleep, time::{Duration, Instant}};
-
-use anyhow::Result;
-use ashpd::{
-    desktop::screencast::{CursorMode, PersistMode, Screencast, SourceType},
-    WindowIdentifier,
+use std::{
+    cell::Cell,
+    io::{IsTerminal, Write},
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use crossbeam_channel::{Receiver, Sender};
+
+use anyhow::{anyhow, Result};
+use args::{Args, CaptureSource, Command};
+use audio::{AudioChunk, AudioOutput};
+use clap::Parser;
+use clock::ClockBurnIn;
+use config::Config;
+use control::Control;
+use cover::Cover;
+use crop::{CropOutput, CropRegion};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use cursor::{ClickHighlight, ClickWatcher, CursorTracker};
+use dump::FrameDumper;
+use frame::{display_aspect, OwnedFrame};
+use image::Image;
+use inline::InlineSender;
+use latency::LatencySimulator;
+use limit::DurationLimit;
+use mirror::{MirrorOutput, MirrorTarget};
 use ndi::NdiLib;
+use overlay::{DebugOverlay, Overlays};
+use pacing::FramePacer;
+use pause::PauseScreen;
+use pip::PictureInPicture;
+use pipeline::{Outputs, Pipeline, Stages};
 use pipewire as pw;
 use pipewire::{main_loop::MainLoop, spa};
+use portal::PortalStream;
+use power::BatteryProfile;
+use profile::Profiler;
+use queue::{DropPolicy, FrameQueue};
+use sched::ThreadScheduling;
+use state::SessionState;
+use stats::Stats;
+use stitch::{Stitcher, Tile};
+use tally::TallyGate;
+use transform::{Size, Transform};
+
+mod args;
+mod audio;
+mod clock;
+mod config;
+mod control;
+mod cover;
+mod crop;
+mod cursor;
+mod discover;
+mod dump;
+mod font;
+mod frame;
+mod health;
+mod image;
+mod inhibit;
+mod inline;
+mod latency;
+mod limit;
+mod mirror;
+mod names;
+mod negotiation;
+mod overlay;
+mod pacing;
+mod pause;
+mod pip;
+mod pipeline;
+mod portal;
+mod power;
+mod profile;
+mod queue;
+mod remote;
+mod rpc;
+mod sandbox;
+mod sched;
+mod schedule;
+mod sink;
+mod state;
+mod stats;
+mod stitch;
+mod systemd;
+mod tally;
+mod test_source;
+mod transform;
+#[cfg(feature = "view")]
+mod view;
+
+/// How often pipeline statistics are published as NDI metadata.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// Name of the main NDI source.
+const SENDER_NAME: &str = "Desktop";
+/// Number of frame buffers rotating between the capture thread and the NDI SDK.
+const BUFFER_POOL_SIZE: usize = 2;
+/// How long the NDI thread waits for a frame before checking the pause state again, and how
+/// often the capture thread checks it with `--low-latency`.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Frame size sent with `--portrait`.
+const PORTRAIT_CANVAS: Size = Size {
+    width: 1080,
+    height: 1920,
+};
+/// How often an idle share checks whether a receiver has connected.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 struct UserData {
     format: spa::param::video::VideoInfoRaw,
+    cursor: CursorTracker,
+    /// Size of the source in logical coordinates, if the portal told.
+    logical_size: Option<(u32, u32)>,
 }
 
-struct OwnedFrame {
-    format: spa::param::video::VideoInfoRaw,
-    create_time: Instant,
-    data: Vec<u8>,
+/// A buffer dequeued from a stream, exposing its metadata alongside the data planes.
+///
+/// The buffer is returned to the stream when dropped.
+struct RawBuffer<'s> {
+    stream: &'s pw::stream::StreamRef,
+    buffer: NonNull<pw::sys::pw_buffer>,
 }
 
-fn ndi_loop(rx: Receiver<OwnedFrame>) -> Result<()> {
-    let ndi_lib = NdiLib::new()?;
-    let sender = ndi_lib.create_sender(Some("Desktop"), None, false, false)?;
+impl<'s> RawBuffer<'s> {
+    fn dequeue(stream: &'s pw::stream::StreamRef) -> Option<Self> {
+        NonNull::new(unsafe { stream.dequeue_raw_buffer() }).map(|buffer| Self { stream, buffer })
+    }
+
+    fn datas_mut(&mut self) -> &mut [spa::buffer::Data] {
+        unsafe {
+            let buffer = self.buffer.as_ref().buffer;
+            if buffer.is_null() || (*buffer).n_datas == 0 || (*buffer).datas.is_null() {
+                return &mut [];
+            }
+            std::slice::from_raw_parts_mut(
+                (*buffer).datas as *mut spa::buffer::Data,
+                (*buffer).n_datas as usize,
+            )
+        }
+    }
+
+    /// Whether the producer marked the whole buffer as corrupted in its header.
+    fn is_corrupted(&self) -> bool {
+        self.find_meta::<spa::sys::spa_meta_header>(spa::sys::SPA_META_Header)
+            .is_some_and(|header| {
+                unsafe { (*header).flags }
+                &spa::sys::SPA_META_HEADER_FLAG_CORRUPTED != 0
+            })
+    }
+
+    /// Looks up the metadata of the given `SPA_META_*` type, if the producer attached it.
+    fn find_meta<T>(&self, type_: u32) -> Option<*const T> {
+        let meta = unsafe {
+            let buffer = self.buffer.as_ref().buffer;
+            if buffer.is_null() {
+                return None;
+            }
+            spa::sys::spa_buffer_find_meta_data(buffer, type_, std::mem::size_of::<T>())
+        };
+        (!meta.is_null()).then_some(meta as *const T)
+    }
+
+    /// Like `find_meta`, also returning the size of the region, which can extend past `T`.
+    fn find_meta_region<T>(&self, type_: u32) -> Option<(*const T, usize)> {
+        unsafe {
+            let buffer = self.buffer.as_ref().buffer;
+            if buffer.is_null() {
+                return None;
+            }
+            let meta = spa::sys::spa_buffer_find_meta(buffer, type_);
+            if meta.is_null() || ((*meta).size as usize) < std::mem::size_of::<T>() {
+                return None;
+            }
+            Some(((*meta).data as *const T, (*meta).size as usize))
+        }
+    }
+}
+
+impl Drop for RawBuffer<'_> {
+    fn drop(&mut self) {
+        unsafe { self.stream.queue_raw_buffer(self.buffer.as_ptr()) }
+    }
+}
+
+/// How frames are captured through the portal.
+#[derive(Clone, Copy)]
+struct CaptureOptions {
+    cursor_meta: bool,
+    virtual_output: bool,
+    debug_negotiation: bool,
+    /// Capture every shared stream and publish them side by side.
+    stitch: bool,
+    /// Keep the permission to share the source, see `--persist`.
+    persist: bool,
+}
+
+/// The NDI sources to publish.
+struct Sources {
+    /// Name of the main source, the crops are named after it.
+    name: String,
+    crops: Vec<CropRegion>,
+    mirrors: Vec<MirrorTarget>,
+}
+
+fn ndi_loop(
+    rx: Receiver<OwnedFrame>,
+    pool: Sender<Vec<u8>>,
+    audio_rx: Receiver<AudioChunk>,
+    stats: Arc<Stats>,
+    control: Arc<Control>,
+    sources: Sources,
+    stages: Stages,
+) -> Result<()> {
+    let mut pipeline = create_pipeline(sources, pool, stats, control.clone(), stages)?;
 
     loop {
-        let mut last_frame = rx.recv()?;
+        if !control.is_active() {
+            if pipeline.connections() == 0 {
+                // Nothing captured while idle is worth sending later.
+                while audio_rx.try_recv().is_ok() {}
+                while let Ok(frame) = rx.try_recv() {
+                    pipeline.discard(frame);
+                }
+                std::thread::sleep(IDLE_POLL_INTERVAL);
+                continue;
+            }
+            println!("Receiver connected, starting capture");
+            control.activate();
+        }
+
+        pipeline.poll_senders(Instant::now());
+        pipeline.poll_stats();
+        pipeline.poll_commands();
+        pipeline.poll_source_label();
+        if pipeline.poll_limit() {
+            return Ok(());
+        }
+        if pipeline.poll_receivers() {
+            continue;
+        }
 
-        if last_frame.create_time.elapsed() > Duration::from_millis(100) {
-            println!("Frame too old, skipping");
+        if pipeline.poll_pause() {
+            // Receivers hear silence while paused.
+            while audio_rx.try_recv().is_ok() {}
+
+            // Keep the capture side running, but discard what it produces.
+            match rx.recv_timeout(PAUSE_POLL_INTERVAL) {
+                Ok(frame) => pipeline.discard(frame),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(e) => return Err(e.into()),
+            }
             continue;
         }
 
-        sender.send(ndi::Frame {
-            width: last_frame.format.size().width,
-            height: last_frame.format.size().height,
-            format: ndi::VideoFormat::BGRX,
-            data: &mut last_frame.data,
-            stride_in_bytes: last_frame.format.size().width * 4,
+        // Wake up in time for frames held back by the latency simulation, for the constant frame
+        // rate and for the cover.
+        let timeout = pipeline.next_due().map_or(STATS_INTERVAL, |due| {
+            due.saturating_duration_since(Instant::now())
         });
+        let waiting = tracing::trace_span!("channel_wait").entered();
+        crossbeam_channel::select! {
+            recv(audio_rx) -> chunk => {
+                drop(waiting);
+                if let Ok(chunk) = chunk {
+                    pipeline.send_audio(chunk);
+                }
+            }
+            recv(rx) -> frame => {
+                drop(waiting);
+                pipeline.send_frame(frame?);
+            }
+            default(timeout) => {}
+        }
+        pipeline.poll_delayed(Instant::now());
+        pipeline.poll_constant_rate(Instant::now());
+        pipeline.poll_cover(Instant::now());
     }
 }
 
-fn pipewire_loop(fd: OwnedFd, node_id: u32, tx: Sender<OwnedFrame>) -> anyhow::Result<()> {
+/// Creates the NDI senders and the pipeline feeding them.
+fn create_pipeline(
+    sources: Sources,
+    pool: Sender<Vec<u8>>,
+    stats: Arc<Stats>,
+    control: Arc<Control>,
+    stages: Stages,
+) -> Result<Pipeline<ndi::Sender>> {
+    let ndi_lib = NdiLib::new()?;
+    let sender = ndi_lib.create_sender(Some(&sources.name), None, false, false)?;
+    let crops = sources
+        .crops
+        .into_iter()
+        .map(|region| {
+            let name = format!("{} - {}", sources.name, region.name);
+            let sender = ndi_lib.create_sender(Some(&name), None, false, false)?;
+            Ok(CropOutput::new(name, region, sender, stages.tally.saving()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mirrors = sources
+        .mirrors
+        .into_iter()
+        .map(|target| {
+            let sender = ndi_lib.create_sender(
+                Some(&target.name),
+                target.groups.as_deref(),
+                false,
+                false,
+            )?;
+            Ok(MirrorOutput::new(target, sender, stages.tally.saving()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let outputs = Outputs {
+        name: sources.name,
+        main: sender,
+        crops,
+        mirrors,
+    };
+    Ok(Pipeline::new(
+        outputs,
+        pool,
+        stats,
+        control,
+        stages,
+        STATS_INTERVAL,
+    ))
+}
+
+fn pipewire_loop(
+    fd: OwnedFd,
+    targets: Vec<(PortalStream, FrameOutput)>,
+    options: CaptureOptions,
+    quit: pw::channel::Receiver<()>,
+) -> anyhow::Result<()> {
     let main_loop = MainLoop::new(None)?;
+    let _quit = quit.attach(main_loop.loop_(), {
+        let main_loop = main_loop.clone();
+        move |_| main_loop.quit()
+    });
     let ctx = pipewire::context::Context::new(&main_loop)?;
+    // All streams share the one connection of the portal, a second client on the same socket
+    // would reuse the ids of the first.
     let core = ctx.connect_fd(fd, None)?;
+    let _captures = targets
+        .into_iter()
+        .map(|(target, output)| add_capture(&main_loop, &core, target, output, options))
+        .collect::<Result<Vec<_>>>()?;
+
+    main_loop.run();
+
+    Ok(())
+}
+
+/// What keeps a stream captured, for as long as it is alive.
+struct Capture<'l> {
+    _listener: pw::stream::StreamListener<UserData>,
+    _timers: Vec<pw::loop_::TimerSource<'l>>,
+    _stream: Rc<pw::stream::Stream>,
+    _label: Option<names::LabelWatch>,
+}
+
+/// Connects a stream capturing `target` into `output` on the loop of `core`.
+fn add_capture<'l>(
+    main_loop: &'l MainLoop,
+    core: &pw::core::Core,
+    target: PortalStream,
+    output: FrameOutput,
+    options: CaptureOptions,
+) -> anyhow::Result<Capture<'l>> {
+    let FrameOutput {
+        queue,
+        pool,
+        stats,
+        control,
+        inline,
+        throttle,
+    } = output;
+    let label = control
+        .map(|control| names::watch_label(core, target.node_id, target.label(), control))
+        .transpose()?;
+    // Without an NDI thread, the pause screen and stats need waking up while nothing is captured.
+    let poll = match inline.clone() {
+        Some(inline) => {
+            let timer = main_loop.loop_().add_timer(move |_| {
+                inline.lock().unwrap().poll();
+            });
+            timer
+                .update_timer(Some(PAUSE_POLL_INTERVAL), Some(PAUSE_POLL_INTERVAL))
+                .into_sync_result()?;
+            Some(timer)
+        }
+        None => None,
+    };
 
     let data = UserData {
         format: Default::default(),
+        cursor: Default::default(),
+        logical_size: target.logical_size,
     };
 
-    let stream = pipewire::stream::Stream::new(
-        &core,
+    let stream = Rc::new(pipewire::stream::Stream::new(
+        core,
         "video-capture",
         pipewire::properties::properties! {
             *pipewire::keys::MEDIA_TYPE => "Video",
             *pipewire::keys::MEDIA_CATEGORY => "Capture",
             *pipewire::keys::MEDIA_ROLE => "Screen",
         },
-    )?;
+    )?);
+    // The stream is stopped while the share is idle.
+    let throttle_timer = match throttle.clone() {
+        Some(throttle) => {
+            let stream = stream.clone();
+            let active = Cell::new(true);
+            let timer = main_loop.loop_().add_timer(move |_| {
+                let wanted = throttle.control.is_active();
+                if active.replace(wanted) != wanted {
+                    if let Err(e) = stream.set_active(wanted) {
+                        eprintln!(
+                            "Failed to {} the stream: {}",
+                            if wanted { "start" } else { "stop" },
+                            e
+                        );
+                    }
+                }
+            });
+            timer
+                .update_timer(Some(IDLE_POLL_INTERVAL), Some(IDLE_POLL_INTERVAL))
+                .into_sync_result()?;
+            Some(timer)
+        }
+        None => None,
+    };
 
-    let _listener = stream
+    let listener = stream
         .add_local_listener_with_user_data(data)
         .state_changed(|_, _, old, new| {
             println!("State changed: {:?} -> {:?}", old, new);
         })
-        .param_changed(|_, user_data, id, param| {
+        .param_changed(move |stream, user_data, id, param| {
+            if options.debug_negotiation {
+                match param {
+                    Some(param) => negotiation::log_param("negotiated", id, param.as_bytes()),
+                    None => println!(
+                        "[negotiation] {:?} cleared",
+                        spa::param::ParamType::from_raw(id)
+                    ),
+                }
+            }
             let Some(param) = param else {
                 return;
             };
@@ -107,17 +489,111 @@ fn pipewire_loop(fd: OwnedFd, node_id: u32, tx: Sender<OwnedFrame>) -> anyhow::R
                 user_data.format.framerate().num,
                 user_data.format.framerate().denom
             );
+            if let Some((width, height)) = user_data.logical_size {
+                println!(
+                    "  logical size: {}x{} (scale {:.2})",
+                    width,
+                    height,
+                    user_data.format.size().width as f64 / width as f64
+                );
+            }
 
             // prepare to render video of this size
+
+            // The crop region tells which part of a buffer holds the picture, scaled buffers
+            // can be padded. The header carries the corrupted flag.
+            let mut metas = vec![
+                meta_param(
+                    spa::sys::SPA_META_Header,
+                    std::mem::size_of::<spa::sys::spa_meta_header>() as i32,
+                    None,
+                ),
+                meta_param(
+                    spa::sys::SPA_META_VideoCrop,
+                    std::mem::size_of::<spa::sys::spa_meta_region>() as i32,
+                    None,
+                ),
+            ];
+            if options.cursor_meta {
+                metas.push(meta_param(
+                    spa::sys::SPA_META_Cursor,
+                    cursor::CURSOR_META_SIZE,
+                    Some((cursor::CURSOR_META_MIN_SIZE, cursor::CURSOR_META_MAX_SIZE)),
+                ));
+            }
+            if options.debug_negotiation {
+                for values in &metas {
+                    negotiation::log_param("offered", spa::sys::SPA_PARAM_Meta, values);
+                }
+            }
+            let mut params: Vec<&spa::pod::Pod> = metas
+                .iter()
+                .map(|values| spa::pod::Pod::from_bytes(values).unwrap())
+                .collect();
+            if let Err(e) = stream.update_params(&mut params) {
+                eprintln!("Failed to request buffer metadata: {}", e);
+            }
+        })
+        .add_buffer(move |_, _, buffer| {
+            if options.debug_negotiation {
+                unsafe { negotiation::log_buffer(buffer) };
+            }
         })
         .process(move |stream, user_data| {
-            match stream.dequeue_buffer() {
+            let read_cursor = |buffer: &RawBuffer, user_data: &mut UserData| {
+                buffer
+                    .find_meta_region::<spa::sys::spa_meta_cursor>(spa::sys::SPA_META_Cursor)
+                    .and_then(|(meta, size)| unsafe { user_data.cursor.update(meta, size) })
+            };
+            // Buffers are handed back uncopied while the NDI thread is busy, the compositor is
+            // not bound to ask again for buffers left queued.
+            if throttle.as_ref().is_some_and(Throttle::is_busy) {
+                while let Some(buffer) = RawBuffer::dequeue(stream) {
+                    read_cursor(&buffer, user_data);
+                }
+                return;
+            }
+            match RawBuffer::dequeue(stream) {
                 None => println!("out of buffers"),
                 Some(mut buffer) => {
+                    let _span = tracing::trace_span!("dequeue").entered();
+                    let mut cursor = read_cursor(&buffer, user_data);
+                    // Only the newest of the buffers left queued is sent, but the cursor updates
+                    // of the others still count.
+                    if throttle.is_some() {
+                        while let Some(newer) = RawBuffer::dequeue(stream) {
+                            buffer = newer;
+                            cursor = read_cursor(&buffer, user_data);
+                        }
+                    }
+                    if buffer.is_corrupted() {
+                        stats.frame_corrupted();
+                        return;
+                    }
+                    let crop = buffer
+                        .find_meta::<spa::sys::spa_meta_region>(spa::sys::SPA_META_VideoCrop)
+                        .map(|meta| unsafe { (*meta).region })
+                        .filter(|region| is_partial(region, user_data.format.size()));
+
                     let datas = buffer.datas_mut();
                     if datas.is_empty() {
                         return;
                     }
+                    // Some drivers hand out buffers they failed to fill, which show up as green or
+                    // garbled flashes if sent.
+                    if datas[0]
+                        .chunk()
+                        .flags()
+                        .contains(spa::buffer::ChunkFlags::CORRUPTED)
+                    {
+                        stats.frame_corrupted();
+                        return;
+                    }
+
+                    let stride = match datas[0].chunk().stride() {
+                        stride if stride > 0 => stride as u32,
+                        _ => user_data.format.size().width * 4,
+                    };
 
                     // copy frame data to screen
                     let data = if let Some(d) = datas[0].data() {
@@ -125,14 +601,64 @@ fn pipewire_loop(fd: OwnedFd, node_id: u32, tx: Sender<OwnedFrame>) -> anyhow::R
                     } else {
                         return;
                     };
+                    // Only the picture is kept, packed without the padding producers may leave
+                    // after each row, so that everything after sees its true size and stride.
+                    let size = user_data.format.size();
+                    let (x, y, width, height) = match crop {
+                        Some(region) => (
+                            region.position.x as usize,
+                            region.position.y as usize,
+                            region.size.width,
+                            region.size.height,
+                        ),
+                        None => (0, 0, size.width, size.height),
+                    };
+                    let row_len = width as usize * 4;
+                    // A buffer too short for the picture is dropped rather than sent with rows
+                    // missing.
+                    let end =
+                        (y + height as usize).saturating_sub(1) * stride as usize + x * 4 + row_len;
+                    if height == 0 || end > data.len() {
+                        return;
+                    }
+
+                    let copying = tracing::trace_span!("copy").entered();
+                    let mut copy = pool.try_recv().unwrap_or_default();
+                    copy.clear();
+                    if x == 0 && stride as usize == row_len {
+                        copy.extend_from_slice(&data[y * row_len..end]);
+                    } else {
+                        for row in y..y + height as usize {
+                            let start = row * stride as usize + x * 4;
+                            copy.extend_from_slice(&data[start..start + row_len]);
+                        }
+                    }
+                    let mut format = user_data.format;
+                    format.set_size(spa::utils::Rectangle { width, height });
+                    let stride = row_len as u32;
 
+                    drop(copying);
+
+                    let size = format.size();
                     let frame = OwnedFrame {
-                        format: user_data.format,
+                        format,
                         create_time: Instant::now(),
-                        data: data.to_vec(),
+                        data: copy,
+                        stride,
+                        cursor,
+                        queued: None,
+                        display_aspect: display_aspect(
+                            size.width,
+                            size.height,
+                            user_data.logical_size,
+                        ),
                     };
 
-                    tx.send(frame).ok();
+                    stats.frame_captured();
+                    match &inline {
+                        Some(inline) => inline.lock().unwrap().send_frame(frame),
+                        None => queue.push(frame),
+                    }
                 }
             }
         })
@@ -200,58 +726,738 @@ fn pipewire_loop(fd: OwnedFd, node_id: u32, tx: Sender<OwnedFrame>) -> anyhow::R
     .0
     .into_inner();
 
+    if options.debug_negotiation {
+        negotiation::log_param(
+            "offered",
+            pw::spa::param::ParamType::EnumFormat.as_raw(),
+            &values,
+        );
+    }
     let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
 
     stream.connect(
         spa::utils::Direction::Input,
-        Some(node_id),
+        Some(target.node_id),
         pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
         &mut params,
     )?;
 
-    main_loop.run();
+    Ok(Capture {
+        _listener: listener,
+        _timers: poll.into_iter().chain(throttle_timer).collect(),
+        _stream: stream,
+        _label: label,
+    })
+}
 
-    Ok(())
+/// Where a capture thread hands its frames, and takes buffers from.
+#[derive(Clone)]
+struct FrameOutput {
+    queue: FrameQueue,
+    pool: Receiver<Vec<u8>>,
+    stats: Arc<Stats>,
+    /// Told what the captured source is called, with `--name-from-source`.
+    control: Option<Arc<Control>>,
+    /// Sends frames right away instead of queueing them, with `--low-latency`.
+    inline: Option<Arc<Mutex<InlineSender>>>,
+    throttle: Option<Throttle>,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let proxy = Screencast::new().await?;
-    let session = proxy.create_session().await?;
-    proxy
-        .select_sources(
-            &session,
-            CursorMode::Embedded,
-            SourceType::Monitor | SourceType::Window,
-            true,
-            None,
-            PersistMode::DoNot,
+/// What holds back capture with `--throttle-capture`.
+#[derive(Clone)]
+struct Throttle {
+    /// Of the main source, whose queue has to be empty before another frame is taken.
+    stats: Arc<Stats>,
+    /// Capture stops while the share is idle.
+    control: Arc<Control>,
+}
+
+impl Throttle {
+    /// Whether the previous frame of the main source is still queued, or the NDI thread is still
+    /// sending it.
+    fn is_busy(&self) -> bool {
+        self.stats.queued_bytes() > 0 || self.stats.is_sending()
+    }
+}
+
+/// Captures through the portal, starting over with a new screencast whenever another source is
+/// requested.
+async fn capture_portal(
+    output: FrameOutput,
+    inset: Option<FrameOutput>,
+    control: &Control,
+    options: CaptureOptions,
+    scheduling: &ThreadScheduling,
+    mut state: SessionState,
+) -> Result<()> {
+    // Only the first screencast may restore the previous source, switching means picking anew.
+    let mut restore_token = state.restore_token.take();
+    loop {
+        let started = portal::start(
+            options.cursor_meta,
+            options.virtual_output,
+            restore_token.as_deref(),
+            options.persist,
         )
-        .await?;
-    let response = proxy
-        .start(&session, &WindowIdentifier::default())
-        .await?
-        .response()?;
+        .await;
+        restore_token = None;
+        let (portal, fd) = match started {
+            Err(e) if e.is::<portal::Denied>() && confirm_retry(&e).await? => continue,
+            result => result?,
+        };
+        let stitched = options.stitch && portal.streams.len() > 1;
+        if options.stitch && !stitched {
+            eprintln!("Only one stream shared, share two or more monitors to stitch them");
+        }
+        let target = if stitched {
+            portal.streams[0]
+        } else {
+            pick_stream(&portal.streams, "Publish").await?
+        };
+        let inset_target = match &inset {
+            Some(_) => pick_inset(&portal.streams, target).await?,
+            None => None,
+        };
 
-    let stream = response.streams().iter().next().unwrap();
-    let node_id = stream.pipe_wire_node_id();
-    let fd = proxy.open_pipe_wire_remote(&session).await?;
+        state.restore_token = portal.restore_token.clone().filter(|_| options.persist);
+        state.node_id = Some(target.node_id);
+        if let Err(e) = state.save() {
+            eprintln!("Failed to save session state: {}", e);
+        }
 
-    let (tx, rx) = crossbeam_channel::unbounded();
+        let mut targets = Vec::new();
+        if let (Some(inset_target), Some(inset)) = (inset_target, &inset) {
+            targets.push((inset_target, inset.clone()));
+        }
+        let stitcher = if stitched {
+            let (tiles, thread) = spawn_stitched(&portal.streams, &output, scheduling);
+            targets.extend(tiles);
+            Some(thread)
+        } else {
+            targets.push((target, output.clone()));
+            None
+        };
+        let (quit, capture) = spawn_capture(fd, targets, options, scheduling);
 
-    let pw_thread = std::thread::spawn(move || {
-        if let Err(e) = pipewire_loop(fd, node_id, tx) {
+        // The label is saved as it changes, to name the sender after it on the next start.
+        loop {
+            tokio::select! {
+                _ = control.source_switch_requested() => break,
+                label = control.source_label_changed() => {
+                    state.label = Some(label);
+                    if let Err(e) = state.save() {
+                        eprintln!("Failed to save session state: {}", e);
+                    }
+                }
+            }
+        }
+        println!("Switching source");
+        let _ = quit.send(());
+        tokio::task::spawn_blocking(move || capture.join())
+            .await?
+            .ok();
+        // Stitching ends by itself once the monitors are no longer captured.
+        if let Some(thread) = stitcher {
+            tokio::task::spawn_blocking(move || thread.join())
+                .await?
+                .ok();
+        }
+        drop(portal);
+    }
+}
+
+/// Captures each of `targets` into its output on a thread of its own until told to quit.
+fn spawn_capture(
+    fd: OwnedFd,
+    targets: Vec<(PortalStream, FrameOutput)>,
+    options: CaptureOptions,
+    scheduling: &ThreadScheduling,
+) -> (pw::channel::Sender<()>, std::thread::JoinHandle<()>) {
+    let (quit_tx, quit_rx) = pw::channel::channel();
+    let scheduling = scheduling.clone();
+    let thread = std::thread::spawn(move || {
+        scheduling.apply("capture");
+        if let Err(e) = pipewire_loop(fd, targets, options, quit_rx) {
             eprintln!("Error: {}", e);
         }
     });
-    let ndi_thread = std::thread::spawn(move || {
-        if let Err(e) = ndi_loop(rx) {
-            eprintln!("Error: {}", e);
+    (quit_tx, thread)
+}
+
+/// Sets up where each of `streams` is captured to, and stitches their frames into the frames of
+/// `output` on a thread of its own.
+fn spawn_stitched(
+    streams: &[PortalStream],
+    output: &FrameOutput,
+    scheduling: &ThreadScheduling,
+) -> (
+    Vec<(PortalStream, FrameOutput)>,
+    std::thread::JoinHandle<()>,
+) {
+    let mut targets = Vec::new();
+    let mut tiles = Vec::new();
+    for stream in streams {
+        // Only the latest frame of each monitor is kept, and they do not count towards the stats.
+        let stats = Arc::new(Stats::default());
+        let (queue, frames) = FrameQueue::new(0, DropPolicy::DropOldest, stats.clone());
+        let (pool_tx, pool) = crossbeam_channel::bounded(BUFFER_POOL_SIZE);
+        tiles.push(Tile::new(stream, frames, pool_tx));
+        let tile = FrameOutput {
+            queue,
+            pool,
+            stats,
+            control: None,
+            inline: None,
+            throttle: None,
+        };
+        targets.push((*stream, tile));
+    }
+
+    let output = output.clone();
+    let scheduling = scheduling.clone();
+    let thread = std::thread::spawn(move || {
+        scheduling.apply("stitch");
+        let mut stitcher = Stitcher::new(tiles);
+        while let Some(frame) = stitcher.next(output.pool.try_recv().unwrap_or_default()) {
+            output.stats.frame_captured();
+            output.queue.push(frame);
         }
     });
+    (targets, thread)
+}
 
-    ndi_thread.join().unwrap();
-    pw_thread.join().unwrap();
+/// Asks on the terminal whether to bring up the portal dialog again, never when running
+/// without one.
+async fn confirm_retry(denied: &anyhow::Error) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
 
+    print!("{}. Try again? [Y/n] ", denied);
+    std::io::stdout().flush()?;
+    let answer = tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line)
+    })
+    .await??;
+
+    Ok(matches!(answer.trim(), "" | "y" | "Y" | "yes"))
+}
+
+/// Lets the user choose on the terminal which of the shared streams to use for `purpose`, taking
+/// the first one when running without a terminal.
+async fn pick_stream(streams: &[PortalStream], purpose: &str) -> Result<PortalStream> {
+    if streams.len() == 1 {
+        return Ok(streams[0]);
+    }
+    if !std::io::stdin().is_terminal() {
+        println!(
+            "{} streams shared, using the first one: {}",
+            streams.len(),
+            streams[0]
+        );
+        return Ok(streams[0]);
+    }
+
+    println!("Shared streams:");
+    for (i, stream) in streams.iter().enumerate() {
+        println!("  {}) {}", i + 1, stream);
+    }
+    loop {
+        print!("{} which one? [1-{}, default 1] ", purpose, streams.len());
+        std::io::stdout().flush()?;
+        let answer = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|_| line)
+        })
+        .await??;
+
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(streams[0]);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=streams.len()).contains(&n) => return Ok(streams[n - 1]),
+            _ => println!("Enter a number between 1 and {}", streams.len()),
+        }
+    }
+}
+
+/// Picks the stream shown as the picture-in-picture inset among the ones not published.
+async fn pick_inset(streams: &[PortalStream], main: PortalStream) -> Result<Option<PortalStream>> {
+    let rest: Vec<PortalStream> = streams
+        .iter()
+        .filter(|stream| stream.node_id != main.node_id)
+        .copied()
+        .collect();
+    if rest.is_empty() {
+        eprintln!("Only one stream shared, share two for picture-in-picture");
+        return Ok(None);
+    }
+    pick_stream(&rest, "Show as the inset").await.map(Some)
+}
+
+/// Waits for SIGINT or SIGTERM.
+async fn terminated() -> Result<()> {
+    let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = term.recv() => {}
+    }
     Ok(())
 }
+
+/// Whether a crop region covers only part of a buffer of the given size, and fits in it.
+fn is_partial(region: &spa::sys::spa_region, size: spa::utils::Rectangle) -> bool {
+    let (x, y) = (region.position.x, region.position.y);
+    let (width, height) = (region.size.width, region.size.height);
+    x >= 0
+        && y >= 0
+        && width > 0
+        && height > 0
+        && x as u32 + width <= size.width
+        && y as u32 + height <= size.height
+        && (width, height) != (size.width, size.height)
+}
+
+/// Serializes a `SPA_PARAM_Meta` object asking the compositor for metadata of type `type_`,
+/// `size` bytes large or within `range`.
+fn meta_param(type_: u32, size: i32, range: Option<(i32, i32)>) -> Vec<u8> {
+    let size = match range {
+        Some((min, max)) => {
+            pw::spa::pod::Value::Choice(pw::spa::pod::ChoiceValue::Int(pw::spa::utils::Choice(
+                pw::spa::utils::ChoiceFlags::empty(),
+                pw::spa::utils::ChoiceEnum::Range {
+                    default: size,
+                    min,
+                    max,
+                },
+            )))
+        }
+        None => pw::spa::pod::Value::Int(size),
+    };
+    let obj = pw::spa::pod::object!(
+        pw::spa::utils::SpaTypes::ObjectParamMeta,
+        pw::spa::param::ParamType::Meta,
+        pw::spa::pod::Property::new(
+            spa::sys::SPA_PARAM_META_type,
+            pw::spa::pod::Value::Id(pw::spa::utils::Id(type_)),
+        ),
+        pw::spa::pod::Property::new(spa::sys::SPA_PARAM_META_size, size),
+    );
+    pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner()
+}
+
+fn main() -> anyhow::Result<()> {
+    // Taken before the NDI SDK or anything else starts threads, which may read the environment
+    // while it is cleared.
+    let activation = systemd::listen_fds().pop();
+    let mut args = Args::parse();
+    if let Some(command) = &args.command {
+        return match command {
+            Command::ListSources { wait, groups } => discover::list_sources(
+                &NdiLib::new()?,
+                Duration::from_secs(*wait),
+                groups.as_deref(),
+            ),
+            #[cfg(feature = "view")]
+            Command::View { source, wait } => {
+                let source =
+                    view::find_source(&NdiLib::new()?, source, Duration::from_secs(*wait))?;
+                view::run(source)
+            }
+        };
+    }
+    let profiler = args.profile.clone().map(Profiler::install).transpose()?;
+    let mut state = if args.resume {
+        let state = SessionState::load()?;
+        println!("Resuming session: {}", state.argv.join(" "));
+        args = Args::try_parse_from(&state.argv)?;
+        state
+    } else {
+        SessionState::new()
+    };
+    if args.low_latency && args.source != CaptureSource::Portal {
+        return Err(anyhow!("--low-latency only works with the portal source"));
+    }
+    if args.stitch && args.source != CaptureSource::Portal {
+        return Err(anyhow!("--stitch only works with the portal source"));
+    }
+    if args.throttle_capture && args.source != CaptureSource::Portal {
+        return Err(anyhow!(
+            "--throttle-capture only works with the portal source"
+        ));
+    }
+    let config = Config::load()?;
+    // The label is only known once capturing, so the name follows the source of the last run.
+    let base_name = match SessionState::load().ok().and_then(|last| last.label) {
+        Some(label) if args.name_from_source => names::with_label(SENDER_NAME, &label),
+        _ => SENDER_NAME.to_string(),
+    };
+    // Looking around the network and taking over block for a while, which is fine as long as
+    // no runtime is running yet.
+    let (sender_name, _name_lock) = names::claim(&NdiLib::new()?, &base_name, args.on_conflict)?;
+    if sender_name != SENDER_NAME {
+        println!("Publishing as \"{}\"", sender_name);
+    }
+    state.sender_name = sender_name.clone();
+
+    // A socket passed by the service manager takes precedence over creating one.
+    let listener = match activation {
+        Some(fd) => {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            println!("Listening for control commands on the activation socket");
+            Some(listener)
+        }
+        None => args
+            .control_socket
+            .clone()
+            .or_else(|| {
+                rpc::default_socket_path(
+                    (sender_name != SENDER_NAME).then_some(sender_name.as_str()),
+                )
+            })
+            .map(|path| rpc::bind(&path))
+            .transpose()?,
+    };
+    // Everything that needs to write outside of the allowed directories is set up by now, and
+    // neither the runtime nor any thread handling frames has been started yet.
+    if args.harden {
+        let writable: Vec<PathBuf> = [
+            state::state_dir(),
+            args.dump_frames.as_ref().map(|target| target.dir.clone()),
+            // A bare file name has an empty parent.
+            args.snapshot
+                .as_deref()
+                .and_then(Path::parent)
+                .map(|dir| {
+                    if dir.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        dir
+                    }
+                })
+                .map(Path::to_path_buf),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        sandbox::harden(&writable)?;
+        println!("Hardened, no programs can be run and files are only written where allowed");
+    }
+
+    // The runtime is only started now, so that its threads are hardened too.
+    tokio::runtime::Runtime::new()?.block_on(run(
+        args,
+        state,
+        config,
+        sender_name,
+        profiler,
+        listener,
+    ))
+}
+
+/// Publishes the screen as `sender_name` until capturing ends.
+async fn run(
+    args: Args,
+    state: SessionState,
+    config: Config,
+    sender_name: String,
+    profiler: Option<Profiler>,
+    listener: Option<std::os::unix::net::UnixListener>,
+) -> Result<()> {
+    let click_highlight = if config.click_highlight.enabled {
+        match ClickWatcher::spawn() {
+            Ok(watcher) => Some(ClickHighlight::new(config.click_highlight.clone(), watcher)),
+            Err(e) => {
+                eprintln!("Click highlight disabled: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    // The cursor has to be drawn by us to know where it is.
+    let cursor_meta = click_highlight.is_some();
+    let stats = Arc::new(Stats::default());
+    let slate = args.slate.as_deref().map(Image::load_png).transpose()?;
+    let pause_screen = PauseScreen::new(args.pause_mode, slate);
+    let cover = args.cover.as_deref().map(Image::load_png).transpose()?;
+    let control = Arc::new(Control::new(args.source));
+    let overlays = Overlays {
+        click_highlight,
+        clock: args
+            .burn_in_clock
+            .then(|| ClockBurnIn::new(args.burn_in_timecode)),
+        debug: args.debug_overlay.then(|| DebugOverlay::new(stats.clone())),
+    };
+
+    let mut usr1 = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())?;
+    let signal_control = control.clone();
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            if signal_control.toggle_pause() {
+                println!("Paused");
+            } else {
+                println!("Resumed");
+            }
+        }
+    });
+
+    if !config.schedule.is_empty() {
+        tokio::spawn(schedule::run(config.schedule.clone(), control.clone()));
+    }
+
+    let scheduling = ThreadScheduling {
+        realtime: args.rt_priority.map(|priority| (args.rt_policy, priority)),
+        cpus: args.cpu_affinity.clone(),
+    };
+
+    let listener = listener
+        .map(tokio::net::UnixListener::from_std)
+        .transpose()?;
+
+    let (queue, rx) = FrameQueue::new(
+        args.queue_budget_mb * 1024 * 1024,
+        args.drop_policy,
+        stats.clone(),
+    );
+    let (pool_tx, pool_rx) = crossbeam_channel::bounded(BUFFER_POOL_SIZE);
+    let throttle = args.throttle_capture.then(|| Throttle {
+        stats: stats.clone(),
+        control: control.clone(),
+    });
+    // Only the latest inset frame is kept, and the inset does not count towards the stats.
+    let (pip, inset) = args
+        .pip
+        .map(|layout| {
+            let stats = Arc::new(Stats::default());
+            let (queue, frames) = FrameQueue::new(0, DropPolicy::DropOldest, stats.clone());
+            let (pool_tx, pool) = crossbeam_channel::bounded(BUFFER_POOL_SIZE);
+            let pip = PictureInPicture::new(layout, frames, pool_tx);
+            let output = FrameOutput {
+                queue,
+                pool,
+                stats,
+                control: None,
+                inline: None,
+                throttle: None,
+            };
+            (pip, output)
+        })
+        .unzip();
+
+    // The sending side is kept here so that a failing audio thread does not disconnect the
+    // channel, which would make the NDI thread spin on it.
+    let (audio_tx, audio_rx) = if args.audio || args.no_video {
+        let (audio_tx, audio_rx) = crossbeam_channel::unbounded();
+        let thread_tx = audio_tx.clone();
+        let scheduling = scheduling.clone();
+        std::thread::spawn(move || {
+            scheduling.apply("audio");
+            if let Err(e) = audio::audio_loop(thread_tx) {
+                eprintln!("Audio error: {}", e);
+            }
+        });
+        (Some(audio_tx), audio_rx)
+    } else {
+        (None, crossbeam_channel::never())
+    };
+    let dumper = args
+        .dump_frames
+        .map(|target| FrameDumper::spawn(target, args.dump_raw))
+        .transpose()?;
+    let audio = AudioOutput::new(args.audio_offset_ms, args.audio_gain_db, args.audio_limiter);
+    let transform = Transform {
+        region: args.region,
+        rotation: args.rotate,
+        canvas: args.canvas.or(args.portrait.then_some(PORTRAIT_CANVAS)),
+    };
+
+    if args.on_demand {
+        control.set_idle();
+        println!("Waiting for a control client or an NDI receiver");
+    }
+
+    if let Some(listener) = listener {
+        let (control, stats) = (control.clone(), stats.clone());
+        tokio::spawn(async move {
+            if let Err(e) = rpc::serve(listener, control, stats).await {
+                eprintln!("Control socket error: {}", e);
+            }
+        });
+    }
+
+    if !args.no_idle_inhibit {
+        let control = control.clone();
+        tokio::spawn(async move {
+            if let Err(e) = inhibit::run(control).await {
+                eprintln!("Cannot inhibit idle: {}", e);
+            }
+        });
+    }
+
+    if config.battery.enabled {
+        let control = control.clone();
+        tokio::spawn(async move {
+            if let Err(e) = power::watch(control).await {
+                eprintln!("Cannot tell whether running on battery: {}", e);
+            }
+        });
+    }
+
+    if let Some(addr) = args.health_addr {
+        let (control, stats) = (control.clone(), stats.clone());
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(addr, control, stats).await {
+                eprintln!("Health endpoint error: {}", e);
+            }
+        });
+    }
+
+    let stages = Stages {
+        pip,
+        overlays,
+        pause_screen,
+        audio,
+        dumper,
+        pacer: FramePacer::new(args.max_fps),
+        battery: config
+            .battery
+            .enabled
+            .then(|| BatteryProfile::new(&config.battery)),
+        tally: TallyGate::new(args.tally_saving),
+        transform,
+        chroma: args.chroma,
+        latency: args.simulate_latency.map(LatencySimulator::new),
+        limit: args
+            .max_duration
+            .map(|max| DurationLimit::new(max, args.exit_at_max_duration)),
+        remote_commands: args.remote_commands,
+        constant_rate: args.cfr.map(|rate| FramePacer::at(Some(rate))),
+        cover: cover.as_ref().map(Cover::new),
+        no_video: args.no_video,
+        throttle_capture: args.throttle_capture,
+    };
+    let sources = Sources {
+        name: sender_name,
+        crops: args.crop,
+        mirrors: args.mirror,
+    };
+
+    // With `--low-latency`, the capture thread sends frames itself and there is no NDI thread.
+    let (inline, ndi_thread) = if args.low_latency {
+        let pipeline = create_pipeline(sources, pool_tx, stats.clone(), control.clone(), stages)?;
+        (
+            Some(Arc::new(Mutex::new(InlineSender::new(pipeline)))),
+            None,
+        )
+    } else {
+        let (ndi_stats, ndi_control) = (stats.clone(), control.clone());
+        let ndi_scheduling = scheduling.clone();
+        let thread = tokio::task::spawn_blocking(move || {
+            ndi_scheduling.apply("send");
+            if let Err(e) = ndi_loop(
+                rx,
+                pool_tx,
+                audio_rx,
+                ndi_stats,
+                ndi_control,
+                sources,
+                stages,
+            ) {
+                eprintln!("Error: {}", e);
+            }
+        });
+        (None, Some(thread))
+    };
+    let ndi_done = async {
+        match ndi_thread {
+            Some(thread) => {
+                thread.await.ok();
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    let capture = async {
+        control.activated().await;
+        // Without video, only the audio thread feeds the sender.
+        if args.no_video {
+            if let Err(e) = state.save() {
+                eprintln!("Failed to save session state: {}", e);
+            }
+            return std::future::pending().await;
+        }
+        match args.source {
+            CaptureSource::Portal => {
+                let options = CaptureOptions {
+                    // Stitched frames carry no cursor, so the compositor has to draw it.
+                    cursor_meta: cursor_meta && !args.stitch,
+                    virtual_output: args.virtual_output,
+                    debug_negotiation: args.debug_negotiation,
+                    stitch: args.stitch,
+                    persist: args.persist,
+                };
+                let output = FrameOutput {
+                    queue,
+                    pool: pool_rx,
+                    stats,
+                    control: args.name_from_source.then(|| control.clone()),
+                    inline,
+                    throttle,
+                };
+                capture_portal(output, inset, &control, options, &scheduling, state).await
+            }
+            CaptureSource::Test => {
+                if let Err(e) = state.save() {
+                    eprintln!("Failed to save session state: {}", e);
+                }
+                std::thread::spawn(move || {
+                    scheduling.apply("capture");
+                    if let Err(e) = test_source::test_source_loop(queue, pool_rx, stats) {
+                        eprintln!("Error: {}", e);
+                    }
+                });
+                std::future::pending().await
+            }
+        }
+    };
+    // With `--snapshot`, the process exits as soon as the first frame is saved.
+    let snapshot = async {
+        match &args.snapshot {
+            Some(path) => control.snapshot(path.clone()).await.map(|()| path),
+            None => std::future::pending().await,
+        }
+    };
+
+    let result = tokio::select! {
+        result = capture => result,
+        _ = ndi_done => Ok(()),
+        path = snapshot => path.map(|path| println!("Saved snapshot to {}", path.display())),
+        // Without a profile to write, signals simply end the process.
+        result = terminated(), if profiler.is_some() => result,
+    };
+    drop(audio_tx);
+    // Written before any error is reported, failed runs being the ones a trace is wanted for.
+    let finished = profiler.map_or(Ok(()), Profiler::finish);
+
+    if let Err(e) = result {
+        if let Err(finish_error) = finished {
+            eprintln!("{:#}", finish_error);
+        }
+        if e.is::<portal::Denied>() {
+            eprintln!("{}", e);
+            std::process::exit(portal::DENIED_EXIT_CODE);
+        }
+        return Err(e);
+    }
+    finished
+}
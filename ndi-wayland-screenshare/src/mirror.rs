@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    frame::{downscale, ndi_format, ndi_stride, Chroma, OwnedFrame},
+    sink::{self, FrameSink},
+    tally::{Quality, TallyGate, TallySaving, REDUCED_SCALE},
+};
+
+/// Another NDI source carrying the same frames as the main one, written as
+/// `NAME[@GROUPS][/N][:CHROMA]`: `GROUPS` is a comma separated list of NDI groups, `N` divides
+/// the resolution and `CHROMA` is `422` or `444`, e.g. `Desktop Proxy/2:422`.
+#[derive(Debug, Clone)]
+pub struct MirrorTarget {
+    pub name: String,
+    pub groups: Option<String>,
+    pub scale: u32,
+    pub chroma: Chroma,
+}
+
+impl FromStr for MirrorTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Mirror must be NAME[@GROUPS][/N][:CHROMA]: {}", s);
+        let (s, chroma) = Chroma::split_suffix(s);
+        let (rest, scale) = match s.rsplit_once('/') {
+            Some((rest, scale)) => {
+                let scale = scale.trim().parse::<u32>().with_context(error)?;
+                (rest, scale)
+            }
+            None => (s, 1),
+        };
+        let (name, groups) = match rest.split_once('@') {
+            Some((name, groups)) => (name, Some(groups.to_string())),
+            None => (rest, None),
+        };
+        if name.is_empty() || scale == 0 || groups.as_deref() == Some("") {
+            return Err(error());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            groups,
+            scale,
+            chroma: chroma.unwrap_or_default(),
+        })
+    }
+}
+
+/// Sends every processed frame through another sender, scaled down if asked to.
+pub struct MirrorOutput<S> {
+    target: MirrorTarget,
+    sender: S,
+    tally: TallyGate,
+    /// Buffer handed back by the SDK, reused for the next frame.
+    spare: Option<Vec<u8>>,
+}
+
+impl<S: FrameSink> MirrorOutput<S> {
+    pub fn new(target: MirrorTarget, sender: S, tally_saving: TallySaving) -> Self {
+        Self {
+            target,
+            sender,
+            tally: TallyGate::new(tally_saving),
+            spare: None,
+        }
+    }
+
+    pub fn send(&mut self, frame: &OwnedFrame, frame_rate: Option<(u32, u32)>) {
+        let scale =
+            match self
+                .tally
+                .check(&self.target.name, self.sender.tally(), frame.create_time)
+            {
+                Quality::Full => self.target.scale,
+                Quality::Reduced => self.target.scale.saturating_mul(REDUCED_SCALE),
+                Quality::Skip => return,
+            };
+
+        let size = frame.format.size();
+        let stride = frame.stride as usize;
+        let (data, width, height) = if scale == 1 {
+            let row_len = size.width as usize * 4;
+            let mut data = self.spare.take().unwrap_or_default();
+            data.clear();
+            for row in 0..size.height as usize {
+                let start = row * stride;
+                let Some(row) = frame.data.get(start..start + row_len) else {
+                    return;
+                };
+                data.extend_from_slice(row);
+            }
+            (data, size.width, size.height)
+        } else {
+            downscale(&frame.data, size.width, size.height, stride, scale)
+        };
+        let (data, format, width) =
+            self.target
+                .chroma
+                .convert(data, width, height, frame.format.format());
+
+        self.spare = self.sender.send_video_async(ndi::Frame {
+            width,
+            height,
+            format: ndi_format(format),
+            data,
+            stride_in_bytes: ndi_stride(format, width),
+            picture_aspect_ratio: frame.display_aspect,
+            frame_rate,
+        });
+    }
+
+    /// Creates the sender again if it stopped working.
+    pub fn recover(&mut self) {
+        sink::recover(&mut self.sender, &self.target.name);
+    }
+
+    #[cfg(test)]
+    pub fn sink(&self) -> &S {
+        &self.sender
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use ndi::NdiLib;
+use pipewire::{self as pw, spa::utils::dict::DictRef, types::ObjectType};
+
+use crate::control::Control;
+
+/// What to do when the sender name is already published from this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Exit with an error.
+    Refuse,
+    /// Append the first free number, as in `Desktop 2`.
+    Suffix,
+    /// Stop the other instance and use the name anyway.
+    TakeOver,
+}
+
+/// Properties of a PipeWire node that may name what it captures, in order of preference.
+const LABEL_KEYS: [&str; 3] = ["node.description", "media.name", "node.nick"];
+/// Parts of the names portal backends give all of their streams alike.
+const GENERIC_LABELS: [&str; 4] = ["screencast", "screen-cast", "xdpw", "portal"];
+/// Labels are cut to this many characters, titles of windows can be long.
+const MAX_LABEL_LEN: usize = 48;
+
+/// Highest number tried by [`OnConflict::Suffix`].
+const MAX_SUFFIX: u32 = 99;
+/// How long discovery may take to list the sources already on the network.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a taken over instance gets to exit.
+const TAKE_OVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Holds a sender name for this process until dropped.
+///
+/// Instances lock a file named after the sender in the runtime directory, which the kernel
+/// releases when the process dies, so stale locks are never an issue. Without a runtime
+/// directory nothing is locked, as other users could tamper with a shared one.
+pub struct NameLock {
+    _file: Option<File>,
+}
+
+/// Picks the name to publish the main source under, making sure no other instance of this app
+/// uses it and no other sender on this machine is already seen on the network with it.
+pub fn claim(ndi: &NdiLib, base: &str, policy: OnConflict) -> Result<(String, NameLock)> {
+    if policy == OnConflict::TakeOver && lock_dir().is_none() {
+        return Err(anyhow!(
+            "--on-conflict take-over needs XDG_RUNTIME_DIR to tell which process to stop"
+        ));
+    }
+    let published = published_names(ndi)?;
+    let candidates = std::iter::once(base.to_string())
+        .chain((2..=MAX_SUFFIX).map(|n| format!("{} {}", base, n)));
+
+    for name in candidates {
+        let lock = try_lock(&name)?;
+        let on_network = published.iter().any(|published| published == &name);
+        match (lock, policy) {
+            (Ok(lock), _) if !on_network => return Ok((name, lock)),
+            (Ok(lock), OnConflict::TakeOver) => {
+                eprintln!(
+                    "\"{}\" is published by another program, which cannot be taken over",
+                    name
+                );
+                return Ok((name, lock));
+            }
+            (Err(pid), OnConflict::TakeOver) => return take_over(name, pid),
+            (_, OnConflict::Refuse) => {
+                return Err(anyhow!(
+                    "\"{}\" is already published from this machine, see --on-conflict",
+                    name
+                ))
+            }
+            (_, OnConflict::Suffix) => {
+                println!("\"{}\" is already published, trying the next name", name)
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No free sender name up to \"{} {}\"",
+        base,
+        MAX_SUFFIX
+    ))
+}
+
+/// Name of the main source when named after what it captures.
+pub fn with_label(base: &str, label: &str) -> String {
+    format!("{} - {}", base, label)
+}
+
+/// What a captured source calls itself according to the properties of its PipeWire node, e.g.
+/// the title of a window or the connector of a monitor. Backends naming every stream the same
+/// tell nothing.
+pub fn source_label(props: &DictRef) -> Option<String> {
+    LABEL_KEYS
+        .iter()
+        .filter_map(|key| props.get(key))
+        .map(str::trim)
+        .find(|label| {
+            let lower = label.to_lowercase();
+            !label.is_empty() && !GENERIC_LABELS.iter().any(|generic| lower.contains(generic))
+        })
+        .map(|label| label.chars().take(MAX_LABEL_LEN).collect())
+}
+
+/// Metadata telling receivers what the main source captures.
+pub fn label_xml(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    format!(r#"<ndi_screenshare_source label="{}"/>"#, escaped)
+}
+
+/// Keeps the label of the captured source up to date while alive.
+pub struct LabelWatch {
+    _registry: Rc<pw::registry::Registry>,
+    _listener: pw::registry::Listener,
+    _node: Rc<RefCell<Option<(pw::node::Node, pw::node::NodeListener)>>>,
+}
+
+/// Follows the properties of the node `node_id` and tells `control` what it is called, starting
+/// from `fallback` until the node names itself.
+pub fn watch_label(
+    core: &pw::core::Core,
+    node_id: u32,
+    fallback: String,
+    control: Arc<Control>,
+) -> Result<LabelWatch> {
+    control.set_source_label(fallback);
+    let registry = Rc::new(core.get_registry()?);
+    let node = Rc::new(RefCell::new(None));
+    let listener = registry
+        .add_listener_local()
+        .global({
+            let (registry, node) = (registry.clone(), node.clone());
+            move |global| {
+                if global.id != node_id || global.type_ != ObjectType::Node {
+                    return;
+                }
+                let proxy: pw::node::Node = match registry.bind(global) {
+                    Ok(proxy) => proxy,
+                    Err(e) => {
+                        eprintln!("Cannot follow the name of the source: {}", e);
+                        return;
+                    }
+                };
+                let control = control.clone();
+                // Properties are sent right away, and again whenever they change.
+                let listener = proxy
+                    .add_listener_local()
+                    .info(move |info| {
+                        if let Some(label) = info.props().and_then(source_label) {
+                            control.set_source_label(label);
+                        }
+                    })
+                    .register();
+                *node.borrow_mut() = Some((proxy, listener));
+            }
+        })
+        .register();
+
+    Ok(LabelWatch {
+        _registry: registry,
+        _listener: listener,
+        _node: node,
+    })
+}
+
+/// Names of the sources this machine publishes, as far as discovery can tell.
+fn published_names(ndi: &NdiLib) -> Result<Vec<String>> {
+    let finder = ndi.create_finder(true, None)?;
+    let started = Instant::now();
+    // Sources trickle in, wait for the list to settle.
+    while started.elapsed() < DISCOVERY_TIMEOUT
+        && finder.wait_for_sources(Duration::from_millis(500))
+    {}
+
+    let host = hostname();
+    Ok(finder
+        .current_sources()
+        .iter()
+        .filter_map(|source| source.machine_and_name())
+        .filter(|(machine, _)| {
+            host.as_deref()
+                .is_some_and(|host| machine.eq_ignore_ascii_case(host))
+        })
+        .map(|(_, name)| name.to_string())
+        .collect())
+}
+
+/// Short host name, which NDI uses as the machine part of source names.
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]);
+    name.split('.').next().map(str::to_string)
+}
+
+/// Directory of the name locks, only in the runtime directory which no other user can write to.
+fn lock_dir() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(dir).join("ndi-wayland-screenshare"))
+}
+
+/// `name` with everything but letters and digits replaced, to name files after a sender.
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Number of instances running on this machine, going by the names they hold.
+pub fn running_instances() -> usize {
+    let Some(Ok(entries)) = lock_dir().map(std::fs::read_dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| File::open(entry.ok()?.path()).ok())
+        .filter(|file| unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } != 0)
+        .count()
+}
+
+/// Locks the file of `name`, or returns the PID of the instance holding it.
+fn try_lock(name: &str) -> Result<Result<NameLock, Option<i32>>> {
+    let Some(dir) = lock_dir() else {
+        return Ok(Ok(NameLock { _file: None }));
+    };
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.lock", file_name(name)));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let mut pid = String::new();
+        file.read_to_string(&mut pid).ok();
+        return Ok(Err(pid.trim().parse().ok()));
+    }
+
+    // The PID is only informational, for taking over.
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())?;
+    Ok(Ok(NameLock { _file: Some(file) }))
+}
+
+fn take_over(name: String, pid: Option<i32>) -> Result<(String, NameLock)> {
+    let pid = pid.ok_or_else(|| anyhow!("Cannot tell which process publishes \"{}\"", name))?;
+    // The lock file only says which PID to stop, anything but another instance is left alone.
+    if !is_this_program(pid) {
+        return Err(anyhow!(
+            "Process {} holding \"{}\" is not an instance of this program, not stopping it",
+            pid,
+            name
+        ));
+    }
+    println!("Taking \"{}\" over from process {}", name, pid);
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to stop process {}", pid));
+    }
+
+    let started = Instant::now();
+    while started.elapsed() < TAKE_OVER_TIMEOUT {
+        if let Ok(lock) = try_lock(&name)? {
+            return Ok((name, lock));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(anyhow!("Process {} did not let go of \"{}\"", pid, name))
+}
+
+/// Whether process `pid` runs the same executable as this one, also when it was started from
+/// a binary that has since been replaced by an upgrade.
+fn is_this_program(pid: i32) -> bool {
+    let Ok(exe) = std::fs::read_link(format!("/proc/{}/exe", pid)) else {
+        return false;
+    };
+    let exe = exe.to_string_lossy();
+    let exe = exe.strip_suffix(" (deleted)").unwrap_or(&exe);
+    std::env::current_exe().is_ok_and(|current| current.to_string_lossy() == exe)
+}
@@ -0,0 +1,83 @@
+//! Logging for `--debug-negotiation`.
+
+use pipewire as pw;
+use pipewire::spa::{self, param::ParamType, pod::deserialize::PodDeserializer};
+
+/// Logs a SPA param both decoded and as a hex dump of the raw pod.
+pub fn log_param(what: &str, id: u32, pod: &[u8]) {
+    println!(
+        "[negotiation] {} {:?} ({} bytes)",
+        what,
+        ParamType::from_raw(id),
+        pod.len()
+    );
+    match PodDeserializer::deserialize_any_from(pod) {
+        Ok((_, value)) => println!("{:#?}", value),
+        Err(e) => println!("  (cannot decode: {:?})", e),
+    }
+    for (i, line) in pod.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("  {:04x}: {}", i * 16, hex.join(" "));
+    }
+}
+
+/// Logs the layout of a buffer handed to the stream.
+///
+/// # Safety
+///
+/// `buffer` must be a valid buffer of a stream, as passed to its `add_buffer` callback.
+pub unsafe fn log_buffer(buffer: *mut pw::sys::pw_buffer) {
+    let buffer = (*buffer).buffer;
+    if buffer.is_null() {
+        return;
+    }
+    let buffer = &*buffer;
+    println!(
+        "[negotiation] buffer added: {} datas, {} metas",
+        buffer.n_datas, buffer.n_metas
+    );
+
+    for i in 0..buffer.n_datas as usize {
+        let data = &*buffer.datas.add(i);
+        println!(
+            "  data {}: type {} flags {:#x} maxsize {} mapoffset {}",
+            i,
+            data_type_name(data.type_),
+            data.flags,
+            data.maxsize,
+            data.mapoffset
+        );
+    }
+    for i in 0..buffer.n_metas as usize {
+        let meta = &*buffer.metas.add(i);
+        println!(
+            "  meta {}: type {} size {}",
+            i,
+            meta_type_name(meta.type_),
+            meta.size
+        );
+    }
+}
+
+fn data_type_name(type_: u32) -> String {
+    match type_ {
+        spa::sys::SPA_DATA_MemPtr => "MemPtr".to_string(),
+        spa::sys::SPA_DATA_MemFd => "MemFd".to_string(),
+        spa::sys::SPA_DATA_DmaBuf => "DmaBuf".to_string(),
+        spa::sys::SPA_DATA_MemId => "MemId".to_string(),
+        other => format!("unknown ({})", other),
+    }
+}
+
+fn meta_type_name(type_: u32) -> String {
+    match type_ {
+        spa::sys::SPA_META_Header => "Header".to_string(),
+        spa::sys::SPA_META_VideoCrop => "VideoCrop".to_string(),
+        spa::sys::SPA_META_VideoDamage => "VideoDamage".to_string(),
+        spa::sys::SPA_META_Bitmap => "Bitmap".to_string(),
+        spa::sys::SPA_META_Cursor => "Cursor".to_string(),
+        spa::sys::SPA_META_Control => "Control".to_string(),
+        spa::sys::SPA_META_Busy => "Busy".to_string(),
+        other => format!("unknown ({})", other),
+    }
+}
@@ -0,0 +1,293 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use pipewire::spa::{param::video::VideoFormat, utils::Fraction};
+use serde::Deserialize;
+
+use crate::{
+    clock::ClockBurnIn,
+    cursor::ClickHighlight,
+    font::{self, GLYPH_HEIGHT, GLYPH_WIDTH},
+    frame::OwnedFrame,
+    stats::{Report, Reporter, Stats},
+};
+
+/// A straight-alpha RGBA color, written as `#rrggbb` or `#rrggbbaa` in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Returns the same color with its alpha scaled by `factor` (0.0 to 1.0).
+    pub fn faded(self, factor: f32) -> Self {
+        Self {
+            a: (self.a as f32 * factor.clamp(0.0, 1.0)) as u8,
+            ..self
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.is_ascii())
+            .ok_or_else(|| anyhow!("Color must be #rrggbb or #rrggbbaa: {}", s))?;
+        let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16);
+
+        match hex.len() {
+            6 => Ok(Self::rgba(channel(0)?, channel(1)?, channel(2)?, 255)),
+            8 => Ok(Self::rgba(
+                channel(0)?,
+                channel(1)?,
+                channel(2)?,
+                channel(3)?,
+            )),
+            _ => Err(anyhow!("Color must be #rrggbb or #rrggbbaa: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+pub const TEXT_COLOR: Color = Color::rgba(255, 255, 255, 255);
+pub const TEXT_BACKGROUND: Color = Color::rgba(0, 0, 0, 160);
+
+/// Mutable view of a packed 32-bit frame for drawing overlays on it.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    /// Whether red is stored in the third byte of a pixel (BGRx/BGRA).
+    bgr: bool,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(
+        data: &'a mut [u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+        format: VideoFormat,
+    ) -> Self {
+        Self {
+            data,
+            width,
+            height,
+            stride,
+            bgr: format == VideoFormat::BGRx || format == VideoFormat::BGRA,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Blends `color` over the pixel at (`x`, `y`), ignoring coordinates outside the frame.
+    pub fn blend(&mut self, x: i32, y: i32, color: Color) {
+        if color.a == 0 || x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return;
+        }
+
+        let offset = y as usize * self.stride + x as usize * 4;
+        let Some(pixel) = self.data.get_mut(offset..offset + 4) else {
+            return;
+        };
+
+        let (r, b) = if self.bgr { (2, 0) } else { (0, 2) };
+        let a = color.a as u32;
+        let mix = |dst: u8, src: u8| ((src as u32 * a + dst as u32 * (255 - a)) / 255) as u8;
+        pixel[r] = mix(pixel[r], color.r);
+        pixel[1] = mix(pixel[1], color.g);
+        pixel[b] = mix(pixel[b], color.b);
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        for py in y..y + height as i32 {
+            for px in x..x + width as i32 {
+                self.blend(px, py, color);
+            }
+        }
+    }
+
+    /// Draws a ring centered on (`cx`, `cy`) covering radii from `inner` to `outer`.
+    pub fn ring(&mut self, cx: i32, cy: i32, outer: u32, inner: u32, color: Color) {
+        let (outer_sq, inner_sq) = ((outer * outer) as i32, (inner * inner) as i32);
+        let r = outer as i32;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let d = dx * dx + dy * dy;
+                if d <= outer_sq && d >= inner_sq {
+                    self.blend(cx + dx, cy + dy, color);
+                }
+            }
+        }
+    }
+
+    /// Blends a tightly packed 32-bit image with its top-left corner at (`x`, `y`).
+    pub fn blit(&mut self, x: i32, y: i32, width: u32, height: u32, pixels: &[u8], bgr: bool) {
+        let (r, b) = if bgr { (2, 0) } else { (0, 2) };
+        for (i, px) in pixels
+            .chunks_exact(4)
+            .take((width * height) as usize)
+            .enumerate()
+        {
+            let color = Color::rgba(px[r], px[1], px[b], px[3]);
+            let i = i as u32;
+            self.blend(x + (i % width) as i32, y + (i / width) as i32, color);
+        }
+    }
+
+    /// Draws one or more lines of text on a box with its top-left corner at (`x`, `y`).
+    pub fn label(&mut self, x: i32, y: i32, scale: u32, text: &str, fg: Color, bg: Color) {
+        let (width, height) = label_size(scale, text);
+        self.fill_rect(x, y, width, height, bg);
+
+        let padding = (scale * LABEL_PADDING) as i32;
+        for (line_no, line) in text.lines().enumerate() {
+            let line_y = y + padding + (line_no as u32 * (GLYPH_HEIGHT + 2) * scale) as i32;
+            for (col, c) in line.chars().enumerate() {
+                let glyph_x = x + padding + (col as u32 * (GLYPH_WIDTH + 1) * scale) as i32;
+                self.glyph(glyph_x, line_y, scale, c, fg);
+            }
+        }
+    }
+
+    fn glyph(&mut self, x: i32, y: i32, scale: u32, c: char, color: Color) {
+        for (row, bits) in font::glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> col) != 0 {
+                    let px = x + (col * scale) as i32;
+                    let py = y + (row as u32 * scale) as i32;
+                    self.fill_rect(px, py, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// Padding around label text, in font pixels.
+const LABEL_PADDING: u32 = 2;
+
+/// Returns the size of the box drawn by [`Canvas::label`].
+pub fn label_size(scale: u32, text: &str) -> (u32, u32) {
+    let columns = text.lines().map(|l| l.chars().count()).max().unwrap_or(0) as u32;
+    let lines = text.lines().count() as u32;
+    // Glyphs are separated by one pixel and lines by two, without any trailing gap.
+    let width = (columns * (GLYPH_WIDTH + 1) * scale).saturating_sub(scale);
+    let height = (lines * (GLYPH_HEIGHT + 2) * scale).saturating_sub(2 * scale);
+    (
+        width + 2 * LABEL_PADDING * scale,
+        height + 2 * LABEL_PADDING * scale,
+    )
+}
+
+/// Picks a text scale that stays readable at any frame size.
+pub fn text_scale(frame_height: u32) -> u32 {
+    (frame_height / 270).max(1)
+}
+
+/// How often the numbers shown by [`DebugOverlay`] are refreshed.
+const DEBUG_OVERLAY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shows pipeline rates and latency in the top-left corner of the frames themselves.
+pub struct DebugOverlay {
+    stats: Arc<Stats>,
+    reporter: Reporter,
+    report: Report,
+}
+
+impl DebugOverlay {
+    pub fn new(stats: Arc<Stats>) -> Self {
+        Self {
+            stats,
+            reporter: Reporter::new(DEBUG_OVERLAY_INTERVAL),
+            report: Report::default(),
+        }
+    }
+
+    fn draw(&mut self, canvas: &mut Canvas) {
+        if let Some(report) = self.reporter.poll(&self.stats) {
+            self.report = report;
+        }
+
+        let audio = match self.stats.audio_levels() {
+            Some(levels) => {
+                let (peak, rms) = levels.max();
+                format!("{:.0} / {:.0} DB", peak, rms)
+            }
+            None => "--".to_string(),
+        };
+        let text = format!(
+            "CAPTURE {:.1} FPS\nSEND    {:.1} FPS\nLATENCY {:.1} MS\nDROPPED {}\nQUEUE   {:.1} MB\nNET     {:.0} MBIT/S\nAUDIO   {}",
+            self.report.capture_fps,
+            self.report.send_fps,
+            self.report.latency_ms,
+            self.report.dropped_total,
+            self.report.queued_mb,
+            self.report.network_mbps,
+            audio
+        );
+        let scale = text_scale(canvas.height());
+        let margin = (8 * scale) as i32;
+        canvas.label(margin, margin, scale, &text, TEXT_COLOR, TEXT_BACKGROUND);
+    }
+}
+
+/// Everything drawn on top of captured frames before they are sent.
+#[derive(Default)]
+pub struct Overlays {
+    pub click_highlight: Option<ClickHighlight>,
+    pub clock: Option<ClockBurnIn>,
+    pub debug: Option<DebugOverlay>,
+}
+
+impl Overlays {
+    /// Draws the enabled overlays on `frame`, which is sent at `frame_rate`.
+    pub fn draw(&mut self, frame: &mut OwnedFrame, frame_rate: Fraction) {
+        if self.click_highlight.is_none() && self.clock.is_none() && self.debug.is_none() {
+            return;
+        }
+
+        let size = frame.format.size();
+        let mut canvas = Canvas::new(
+            &mut frame.data,
+            size.width,
+            size.height,
+            frame.stride as usize,
+            frame.format.format(),
+        );
+
+        if let (Some(highlight), Some(cursor)) = (&self.click_highlight, &frame.cursor) {
+            highlight.draw(&mut canvas, cursor);
+        }
+        if let Some(clock) = &self.clock {
+            clock.draw(&mut canvas, frame.create_time, frame_rate);
+        }
+        if let Some(debug) = &mut self.debug {
+            debug.draw(&mut canvas);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use pipewire::spa::utils::Fraction;
+
+/// Highest frame rate accepted, anything above is a typo.
+const MAX_RATE: u32 = 1000;
+
+/// Parses a frame rate written as a whole number, a fraction or a decimal, e.g. `60`,
+/// `30000/1001` or `29.97`. Decimals close to an NTSC-family rate are taken as exactly that.
+pub fn parse_rate(s: &str) -> Result<Fraction> {
+    let error = || anyhow!("Frame rate must be like 60, 30000/1001 or 29.97: {}", s);
+    let s = s.trim();
+    let (num, denom) = if let Some((num, denom)) = s.split_once('/') {
+        let num = num.trim().parse::<u32>().map_err(|_| error())?;
+        (num, denom.trim().parse::<u32>().map_err(|_| error())?)
+    } else if let Ok(fps) = s.parse::<u32>() {
+        (fps, 1)
+    } else {
+        let fps: f64 = s.parse().map_err(|_| error())?;
+        if !(fps > 0.0 && fps <= MAX_RATE as f64) {
+            return Err(error());
+        }
+        let ntsc = (fps * 1.001).round();
+        if (ntsc * 1000.0 / 1001.0 - fps).abs() < 0.01 {
+            ((ntsc as u32).checked_mul(1000).ok_or_else(error)?, 1001)
+        } else {
+            ((fps * 1000.0).round() as u32, 1000)
+        }
+    };
+    let rate = Fraction { num, denom };
+    let max = Fraction {
+        num: MAX_RATE,
+        denom: 1,
+    };
+    if num == 0 || denom == 0 || slower(max, rate) {
+        return Err(error());
+    }
+    Ok(rate)
+}
+
+/// Whether rate `a` is lower than rate `b`.
+fn slower(a: Fraction, b: Fraction) -> bool {
+    (a.num as u64 * b.denom as u64) < (b.num as u64 * a.denom as u64)
+}
+
+/// Limits how often frames go out on one source, skipping the ones in between.
+#[derive(Debug, Clone, Default)]
+pub struct FramePacer {
+    rate: Option<Fraction>,
+    /// Start of the current schedule and how many frames were let through since. Due times are
+    /// counted from there instead of adding up intervals, so fractional rates do not drift.
+    origin: Option<Instant>,
+    frames: u64,
+}
+
+impl FramePacer {
+    /// Lets at most `max_fps` frames through per second, or all of them if `None`.
+    pub fn new(max_fps: Option<u32>) -> Self {
+        Self::at(max_fps.map(|fps| Fraction {
+            num: fps.max(1),
+            denom: 1,
+        }))
+    }
+
+    /// Lets at most `rate` frames through per second, or all of them if `None`.
+    pub fn at(rate: Option<Fraction>) -> Self {
+        Self {
+            rate: rate.filter(|rate| rate.num > 0 && rate.denom > 0),
+            origin: None,
+            frames: 0,
+        }
+    }
+
+    pub fn rate(&self) -> Option<Fraction> {
+        self.rate
+    }
+
+    /// The rate frames at `source` rate leave with, the lower of both.
+    pub fn limit(&self, source: Fraction) -> Fraction {
+        match self.rate {
+            Some(rate) if source.num == 0 || source.denom == 0 || slower(rate, source) => rate,
+            _ => source,
+        }
+    }
+
+    /// Returns whether a frame arriving at `now` should be sent.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        let (Some(rate), Some(origin)) = (self.rate, self.origin) else {
+            self.origin = self.rate.map(|_| now);
+            self.frames = 1;
+            return true;
+        };
+        if now < due(rate, origin, self.frames) {
+            return false;
+        }
+
+        // Keep to the schedule so that capture jitter does not lower the rate, but never catch
+        // up on more than one interval after a gap.
+        if now < due(rate, origin, self.frames + 1) {
+            self.frames += 1;
+        } else {
+            self.origin = Some(now);
+            self.frames = 1;
+        }
+        true
+    }
+
+    /// When the next frame is let through, `None` if that is right away.
+    pub fn next_ready(&self) -> Option<Instant> {
+        let (rate, origin) = (self.rate?, self.origin?);
+        Some(due(rate, origin, self.frames))
+    }
+}
+
+/// When frame number `frames` of the schedule started at `origin` is due.
+fn due(rate: Fraction, origin: Instant, frames: u64) -> Instant {
+    let nanos = frames as u128 * 1_000_000_000 * rate.denom as u128 / rate.num as u128;
+    origin + Duration::from_nanos(nanos as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(s: &str) -> Option<(u32, u32)> {
+        parse_rate(s).ok().map(|rate| (rate.num, rate.denom))
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(rate("60"), Some((60, 1)));
+        assert_eq!(rate(" 30000/1001 "), Some((30000, 1001)));
+        assert_eq!(rate("29.97"), Some((30000, 1001)));
+        assert_eq!(rate("59.94"), Some((60000, 1001)));
+        assert_eq!(rate("12.5"), Some((12500, 1000)));
+    }
+
+    #[test]
+    fn rejects_absurd_rates() {
+        for s in ["0", "0/1", "1/0", "-5", "nan", "inf", "-inf", "", "fast"] {
+            assert_eq!(rate(s), None, "{}", s);
+        }
+        assert_eq!(rate("1000"), Some((1000, 1)));
+        for s in ["1001", "1000.5", "5000000.5", "4294967295/1"] {
+            assert_eq!(rate(s), None, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use pipewire::spa::param::video::{VideoFormat, VideoInfoRaw};
+
+use crate::{
+    frame::{ndi_format, ndi_stride},
+    image::Image,
+    sink::FrameSink,
+};
+
+/// What receivers see while streaming is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PauseMode {
+    /// Keep showing the last frame sent before pausing.
+    Freeze,
+    /// Send a black frame.
+    Black,
+    /// Send a custom image.
+    Slate,
+}
+
+/// How often the pause frame is repeated, so receivers do not consider the source gone.
+const PAUSE_REFRESH: Duration = Duration::from_secs(1);
+
+struct PauseFrame {
+    width: u32,
+    height: u32,
+    format: VideoFormat,
+    data: Vec<u8>,
+}
+
+/// Produces the frames sent in place of the capture while paused.
+pub struct PauseScreen {
+    mode: PauseMode,
+    /// Mode to go back to once the pause forced to black ends.
+    restore: Option<PauseMode>,
+    slate: Option<Image>,
+    active: bool,
+    frame: Option<PauseFrame>,
+    last_sent: Option<Instant>,
+}
+
+impl PauseScreen {
+    pub fn new(mode: PauseMode, slate: Option<Image>) -> Self {
+        Self {
+            mode,
+            restore: None,
+            slate,
+            active: false,
+            frame: None,
+            last_sent: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Changes what is shown from the next pause on.
+    pub fn set_mode(&mut self, mode: PauseMode) {
+        match &mut self.restore {
+            Some(restore) => *restore = mode,
+            None => self.mode = mode,
+        }
+    }
+
+    /// Shows black during the next pause whatever the mode, which applies again after it.
+    pub fn force_black(&mut self) {
+        self.restore.get_or_insert(self.mode);
+        self.mode = PauseMode::Black;
+    }
+
+    /// Prepares the pause frame at the size of the last frame sent, whose buffer is `last`.
+    ///
+    /// Returns `last` back if it is not needed.
+    pub fn begin(
+        &mut self,
+        format: Option<VideoInfoRaw>,
+        last: Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        self.active = true;
+        self.last_sent = None;
+
+        let (width, height, format) = match (format, &self.slate) {
+            (Some(f), _) => (f.size().width, f.size().height, f.format()),
+            (None, Some(slate)) => (slate.width, slate.height, VideoFormat::BGRx),
+            (None, None) => return last,
+        };
+        // Black and slate frames are drawn in RGB, only a frozen frame stays subsampled.
+        let format = match format {
+            VideoFormat::UYVY if self.mode != PauseMode::Freeze => VideoFormat::BGRx,
+            _ => format,
+        };
+        let bgr = format == VideoFormat::BGRx || format == VideoFormat::BGRA;
+
+        let (data, unused) = match self.mode {
+            PauseMode::Freeze => match last {
+                Some(last) => (last, None),
+                None => return None,
+            },
+            // Opaque, receivers keying on alpha would show through otherwise.
+            PauseMode::Black => (
+                [0, 0, 0, 255].repeat(width as usize * height as usize),
+                last,
+            ),
+            PauseMode::Slate => match &self.slate {
+                Some(slate) => (slate.render(width, height, bgr), last),
+                None => return last,
+            },
+        };
+
+        self.frame = Some(PauseFrame {
+            width,
+            height,
+            format,
+            data,
+        });
+        unused
+    }
+
+    /// Stops pausing, returning the buffer of a frozen frame for reuse.
+    pub fn end(&mut self) -> Option<Vec<u8>> {
+        self.active = false;
+        let frozen = self.mode == PauseMode::Freeze;
+        if let Some(mode) = self.restore.take() {
+            self.mode = mode;
+        }
+        let frame = self.frame.take()?;
+        frozen.then_some(frame.data)
+    }
+
+    /// Sends the pause frame if it is due.
+    pub fn poll(&mut self, sink: &mut impl FrameSink) {
+        let Some(frame) = &mut self.frame else {
+            return;
+        };
+        if self.last_sent.is_some_and(|t| t.elapsed() < PAUSE_REFRESH) {
+            return;
+        }
+
+        sink.send_video(ndi::Frame {
+            width: frame.width,
+            height: frame.height,
+            format: ndi_format(frame.format),
+            data: &mut frame.data[..],
+            stride_in_bytes: ndi_stride(frame.format, frame.width),
+            picture_aspect_ratio: None,
+            frame_rate: None,
+        });
+        self.last_sent = Some(Instant::now());
+    }
+}
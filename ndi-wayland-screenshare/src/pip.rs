@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use crossbeam_channel::{Receiver, Sender};
+use pipewire::spa::param::video::VideoFormat;
+
+use crate::{frame::OwnedFrame, image::Image};
+
+/// Corner of the frame the inset is placed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Where and how large the inset is drawn, written as `CORNER[,PERCENT[,MARGIN]]`, e.g.
+/// `bottom-right,25,16`.
+#[derive(Debug, Clone, Copy)]
+pub struct PipLayout {
+    pub corner: Corner,
+    /// Width of the inset in percent of the width of the main capture.
+    pub percent: u32,
+    /// Distance from the edges of the frame, in pixels.
+    pub margin: u32,
+}
+
+impl FromStr for PipLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            anyhow!(
+                "Picture-in-picture must be CORNER[,PERCENT[,MARGIN]]: {}",
+                s
+            )
+        };
+        let mut parts = s.split(',').map(str::trim);
+        let corner = match parts.next() {
+            Some("top-left") => Corner::TopLeft,
+            Some("top-right") => Corner::TopRight,
+            Some("bottom-left") => Corner::BottomLeft,
+            Some("bottom-right") => Corner::BottomRight,
+            _ => return Err(error()),
+        };
+        let number = |v: Option<&str>, default: u32| {
+            v.map_or(Ok(default), |v| v.parse::<u32>().with_context(error))
+        };
+        let percent = number(parts.next(), 25)?;
+        let margin = number(parts.next(), 16)?;
+        if !(1..=100).contains(&percent) || parts.next().is_some() {
+            return Err(error());
+        }
+
+        Ok(Self {
+            corner,
+            percent,
+            margin,
+        })
+    }
+}
+
+/// The inset scaled for the frames it is drawn on.
+struct Rendered {
+    /// Size and channel order of the frames it was rendered for.
+    frame: (u32, u32, bool),
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Composites the latest frame of a second capture as an inset over the main one.
+pub struct PictureInPicture {
+    layout: PipLayout,
+    frames: Receiver<OwnedFrame>,
+    /// Buffers of inset frames are handed back to their capture through here.
+    pool: Sender<Vec<u8>>,
+    latest: Option<Image>,
+    rendered: Option<Rendered>,
+}
+
+impl PictureInPicture {
+    pub fn new(layout: PipLayout, frames: Receiver<OwnedFrame>, pool: Sender<Vec<u8>>) -> Self {
+        Self {
+            layout,
+            frames,
+            pool,
+            latest: None,
+            rendered: None,
+        }
+    }
+
+    /// Draws the latest inset frame over `frame`, nothing before the first one arrived.
+    pub fn draw(&mut self, frame: &mut OwnedFrame) {
+        // Only the newest frame matters, and converting it right away frees its buffer.
+        if let Some(inset) = self.frames.try_iter().last() {
+            self.latest = Some(Image::from_frame(inset.format, inset.stride, &inset.data));
+            self.rendered = None;
+            self.pool.try_send(inset.data).ok();
+        }
+        let Some(image) = &self.latest else {
+            return;
+        };
+
+        let size = frame.format.size();
+        let format = frame.format.format();
+        let bgr = format == VideoFormat::BGRx || format == VideoFormat::BGRA;
+        let key = (size.width, size.height, bgr);
+        if self.rendered.as_ref().map(|r| r.frame) != Some(key) {
+            self.rendered = render(image, &self.layout, key);
+        }
+        let Some(rendered) = &self.rendered else {
+            return;
+        };
+
+        let stride = frame.stride as usize;
+        let row_len = rendered.width as usize * 4;
+        for (row, pixels) in rendered.pixels.chunks_exact(row_len).enumerate() {
+            let start = (rendered.y as usize + row) * stride + rendered.x as usize * 4;
+            if let Some(dst) = frame.data.get_mut(start..start + row_len) {
+                dst.copy_from_slice(pixels);
+            }
+        }
+    }
+}
+
+/// Scales `image` for frames of the given size, `None` if it does not fit at all.
+fn render(image: &Image, layout: &PipLayout, frame: (u32, u32, bool)) -> Option<Rendered> {
+    let (frame_width, frame_height, bgr) = frame;
+    if image.width == 0 || image.height == 0 {
+        return None;
+    }
+
+    let margin = layout.margin;
+    let max_width = frame_width.checked_sub(margin.checked_mul(2)?)?;
+    let max_height = frame_height.checked_sub(margin.checked_mul(2)?)?;
+    let mut width = (frame_width * layout.percent / 100).min(max_width);
+    let mut height = (width as u64 * image.height as u64 / image.width as u64) as u32;
+    if height > max_height {
+        height = max_height;
+        width = (height as u64 * image.width as u64 / image.height as u64) as u32;
+    }
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let (x, y) = match layout.corner {
+        Corner::TopLeft => (margin, margin),
+        Corner::TopRight => (frame_width - margin - width, margin),
+        Corner::BottomLeft => (margin, frame_height - margin - height),
+        Corner::BottomRight => (frame_width - margin - width, frame_height - margin - height),
+    };
+    Some(Rendered {
+        frame,
+        x,
+        y,
+        width,
+        height,
+        pixels: image.render(width, height, bgr),
+    })
+}
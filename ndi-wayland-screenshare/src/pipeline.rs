@@ -0,0 +1,1405 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::Sender;
+use pipewire::spa::{
+    param::video::{VideoFormat, VideoInfoRaw},
+    utils::{Fraction, Rectangle},
+};
+
+use crate::{
+    args::CaptureSource,
+    audio::{AudioChunk, AudioOutput},
+    control::Control,
+    cover::Cover,
+    crop::CropOutput,
+    dump::FrameDumper,
+    frame::{downscale, ndi_format, ndi_rate, ndi_stride, to_uyvy, Chroma, OwnedFrame},
+    image::Image,
+    latency::LatencySimulator,
+    limit::DurationLimit,
+    mirror::MirrorOutput,
+    names,
+    overlay::Overlays,
+    pacing::FramePacer,
+    pause::{PauseMode, PauseScreen},
+    pip::PictureInPicture,
+    power::BatteryProfile,
+    remote::{Command, CommandKind},
+    sink::{self, FrameSink},
+    stats::{Reporter, Stats},
+    tally::{Quality, TallyGate, REDUCED_SCALE},
+    transform::Transform,
+};
+
+/// Frames captured longer ago than this are dropped instead of being sent late.
+const MAX_FRAME_AGE: Duration = Duration::from_millis(100);
+
+/// How often the senders are checked for having stopped working.
+const SENDER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Optional processing steps applied on the NDI thread.
+pub struct Stages {
+    /// Second capture drawn over the first, below the overlays.
+    pub pip: Option<PictureInPicture>,
+    pub overlays: Overlays,
+    pub pause_screen: PauseScreen,
+    pub audio: AudioOutput,
+    pub dumper: Option<FrameDumper>,
+    /// Frame rate limit of the main source.
+    pub pacer: FramePacer,
+    /// Applied on top of the other limits while running on battery.
+    pub battery: Option<BatteryProfile>,
+    /// Shape of the main source, applied after crops have been taken.
+    pub transform: Transform,
+    /// Chroma resolution of the main source, applied last.
+    pub chroma: Chroma,
+    pub tally: TallyGate,
+    /// Delays frames before anything else is done with them, for testing.
+    pub latency: Option<LatencySimulator>,
+    /// Stops sharing after `--max-duration`.
+    pub limit: Option<DurationLimit>,
+    /// Commands receivers may send as metadata.
+    pub remote_commands: Vec<CommandKind>,
+    /// Sends the main source at a constant rate when set, repeating the last frame when nothing
+    /// new was captured.
+    pub constant_rate: Option<FramePacer>,
+    /// Sent as the only video of an audio-only source.
+    pub cover: Option<Cover>,
+    /// Nothing is captured with `--no-video`, so sending audio counts as streaming.
+    pub no_video: bool,
+    /// Goes idle once the last receiver left, see `--throttle-capture`.
+    pub throttle_capture: bool,
+}
+
+/// The NDI sources fed by the pipeline.
+pub struct Outputs<S> {
+    /// Name of the main source.
+    pub name: String,
+    pub main: S,
+    pub crops: Vec<CropOutput<S>>,
+    pub mirrors: Vec<MirrorOutput<S>>,
+}
+
+/// Everything the NDI thread does to frames and audio on their way out, independent of where
+/// they come from and where they go.
+pub struct Pipeline<S> {
+    name: String,
+    sink: S,
+    crops: Vec<CropOutput<S>>,
+    mirrors: Vec<MirrorOutput<S>>,
+    /// Buffers are handed back to the capture side through here.
+    pool: Sender<Vec<u8>>,
+    stats: Arc<Stats>,
+    control: Arc<Control>,
+    stages: Stages,
+    reporter: Reporter,
+    last_format: Option<VideoInfoRaw>,
+    last_aspect: Option<f32>,
+    last_rate: Option<(u32, u32)>,
+    /// Label of the captured source receivers were last told about.
+    source_label: Option<String>,
+    /// Latest frame waiting for the next tick of the constant rate.
+    pending: Option<OwnedFrame>,
+    /// Frames held back by the latency simulation, with when they are due.
+    delayed: VecDeque<(Instant, OwnedFrame)>,
+    next_sender_check: Instant,
+}
+
+impl<S: FrameSink> Pipeline<S> {
+    pub fn new(
+        outputs: Outputs<S>,
+        pool: Sender<Vec<u8>>,
+        stats: Arc<Stats>,
+        control: Arc<Control>,
+        stages: Stages,
+        stats_interval: Duration,
+    ) -> Self {
+        Self {
+            name: outputs.name,
+            sink: outputs.main,
+            crops: outputs.crops,
+            mirrors: outputs.mirrors,
+            pool,
+            stats,
+            control,
+            stages,
+            reporter: Reporter::new(stats_interval),
+            last_format: None,
+            last_aspect: None,
+            last_rate: None,
+            source_label: None,
+            pending: None,
+            delayed: VecDeque::new(),
+            next_sender_check: Instant::now(),
+        }
+    }
+
+    /// Publishes pipeline statistics as metadata when they are due.
+    pub fn poll_stats(&mut self) {
+        // Kept current for the bandwidth estimate, asking the SDK is cheap.
+        self.stats.set_connections(self.sink.connections());
+        if let Some(report) = self.reporter.poll(&self.stats) {
+            self.sink.send_metadata(&report.to_xml());
+            self.stats.set_last_report(report);
+        }
+    }
+
+    /// Carries out the allowed commands receivers sent as metadata.
+    pub fn poll_commands(&mut self) {
+        if self.stages.remote_commands.is_empty() {
+            return;
+        }
+
+        while let Some(metadata) = self.sink.receive_metadata() {
+            let command = match Command::parse(&metadata) {
+                Some(Ok(command)) => command,
+                Some(Err(e)) => {
+                    eprintln!("Ignoring remote command: {}", e);
+                    continue;
+                }
+                None => continue,
+            };
+            if !self.stages.remote_commands.contains(&command.kind()) {
+                eprintln!("Ignoring remote command, not allowed: {:?}", command);
+                continue;
+            }
+
+            println!("Remote command: {:?}", command);
+            match command {
+                Command::Pause(mode) => {
+                    if let Some(mode) = mode {
+                        self.stages.pause_screen.set_mode(mode);
+                    }
+                    self.control.set_paused(true);
+                }
+                Command::Resume => self.control.set_paused(false),
+                Command::Fps(fps) => match (&mut self.stages.constant_rate, fps) {
+                    (Some(clock), Some(fps)) => *clock = FramePacer::at(Some(fps)),
+                    _ => self.stages.pacer = FramePacer::at(fps),
+                },
+                Command::SelectSource if self.control.source() != CaptureSource::Portal => {
+                    eprintln!("Only portal captures can switch sources")
+                }
+                Command::SelectSource => self.control.request_source_switch(),
+            }
+        }
+    }
+
+    /// Tells receivers, present and future, what the captured source is called once it changes.
+    pub fn poll_source_label(&mut self) {
+        let Some(label) = self.control.source_label() else {
+            return;
+        };
+        if self.source_label.as_ref() == Some(&label) {
+            return;
+        }
+        let xml = names::label_xml(&label);
+        self.sink.set_connection_metadata(&xml);
+        self.sink.send_metadata(&xml);
+        self.source_label = Some(label);
+    }
+
+    /// Creates the senders the SDK gave up on again under the same name, instead of sending into
+    /// them forever.
+    pub fn poll_senders(&mut self, now: Instant) {
+        if now < self.next_sender_check {
+            return;
+        }
+        self.next_sender_check = now + SENDER_CHECK_INTERVAL;
+
+        if sink::recover(&mut self.sink, &self.name) {
+            // The new sender has yet to be told the label.
+            self.source_label = None;
+        }
+        for crop in &mut self.crops {
+            crop.recover();
+        }
+        for mirror in &mut self.mirrors {
+            mirror.recover();
+        }
+    }
+
+    /// Stops capturing once the last receiver left with `--throttle-capture`, returning whether
+    /// it did. Capture starts again when the next one connects, like with `--on-demand`.
+    pub fn poll_receivers(&mut self) -> bool {
+        if !self.stages.throttle_capture || self.sink.connections() > 0 {
+            return false;
+        }
+        println!("No receiver left, stopping capture");
+        self.control.set_idle();
+        true
+    }
+
+    /// Stops sharing the desktop once the maximum duration is reached, returning whether to exit.
+    pub fn poll_limit(&mut self) -> bool {
+        let Some(limit) = &mut self.stages.limit else {
+            return false;
+        };
+        if !limit.expire(Instant::now()) {
+            return false;
+        }
+        if limit.exit {
+            println!("Maximum duration reached, exiting");
+            return true;
+        }
+
+        println!("Maximum duration reached, sending black until resumed");
+        self.stages.pause_screen.force_black();
+        self.control.set_paused(true);
+        false
+    }
+
+    /// Number of receivers connected to the main source.
+    pub fn connections(&self) -> u32 {
+        self.sink.connections()
+    }
+
+    /// Follows the paused state, sending the pause frame while paused.
+    ///
+    /// Returns whether the pipeline is paused.
+    pub fn poll_pause(&mut self) -> bool {
+        let pause_screen = &mut self.stages.pause_screen;
+        if self.control.is_paused() {
+            // Frames held back from before the pause must not show up after it.
+            for (_, frame) in std::mem::take(&mut self.delayed) {
+                self.pool.try_send(frame.data).ok();
+            }
+            if let Some(frame) = self.pending.take() {
+                self.pool.try_send(frame.data).ok();
+            }
+            if !pause_screen.is_active() {
+                // Take back the last frame from the SDK so that it can be frozen.
+                let last = self.sink.flush_video();
+                if let Some(buffer) = pause_screen.begin(self.last_format, last) {
+                    self.recycle(buffer);
+                }
+            }
+            self.stages.pause_screen.poll(&mut self.sink);
+            true
+        } else {
+            if pause_screen.is_active() {
+                if let Some(buffer) = pause_screen.end() {
+                    self.recycle(buffer);
+                }
+            }
+            false
+        }
+    }
+
+    /// Disposes of a frame captured while paused.
+    pub fn discard(&mut self, frame: OwnedFrame) {
+        self.serve_snapshots(&frame);
+        self.recycle(frame.data);
+    }
+
+    pub fn send_frame(&mut self, frame: OwnedFrame) {
+        self.stats.set_sending(true);
+        match &mut self.stages.latency {
+            Some(latency) => {
+                let due = latency.due(Instant::now());
+                self.delayed.push_back((due, frame));
+            }
+            None => self.process_frame(frame),
+        }
+        self.stats.set_sending(false);
+    }
+
+    /// When the next frame held back by the latency simulation, the next tick of the constant
+    /// rate or the cover is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        let delayed = self.delayed.front().map(|(due, _)| *due);
+        let tick = self
+            .stages
+            .constant_rate
+            .as_ref()
+            .map(|clock| clock.next_ready().unwrap_or_else(Instant::now));
+        let cover = self.stages.cover.as_ref().map(Cover::next_due);
+        delayed.into_iter().chain(tick).chain(cover).min()
+    }
+
+    /// Sends the cover of an audio-only source if it is due at `now`.
+    pub fn poll_cover(&mut self, now: Instant) {
+        if let Some(cover) = &mut self.stages.cover {
+            if cover.poll(&mut self.sink, now) {
+                self.stats.audio_only_sent();
+            }
+        }
+    }
+
+    /// Sends the frames held back by the latency simulation that are due at `now`.
+    pub fn poll_delayed(&mut self, now: Instant) {
+        while self.delayed.front().is_some_and(|(due, _)| *due <= now) {
+            if let Some((_, frame)) = self.delayed.pop_front() {
+                self.process_frame(frame);
+            }
+        }
+    }
+
+    /// Sends a frame on the main source if the constant rate ticks at `now`, the latest one
+    /// captured or the last one sent again if there is none.
+    pub fn poll_constant_rate(&mut self, now: Instant) {
+        let Some(clock) = &mut self.stages.constant_rate else {
+            return;
+        };
+        if !clock.ready(now) {
+            return;
+        }
+        match self.pending.take() {
+            Some(frame) => self.send_main(frame),
+            None => self.repeat_last(),
+        }
+    }
+
+    fn process_frame(&mut self, mut frame: OwnedFrame) {
+        if let Some(dumper) = &mut self.stages.dumper {
+            dumper.capture(&frame);
+        }
+        self.serve_snapshots(&frame);
+
+        if frame.create_time.elapsed() > MAX_FRAME_AGE {
+            println!("Frame too old, skipping");
+            self.stats.frame_dropped();
+            self.recycle(frame.data);
+            return;
+        }
+
+        if let Some(pip) = &mut self.stages.pip {
+            tracing::trace_span!("pip").in_scope(|| pip.draw(&mut frame));
+        }
+        // The timecode is counted at the rate the frame leaves with.
+        let rate = self
+            .outgoing_rate(&frame)
+            .unwrap_or(Fraction { num: 0, denom: 1 });
+        tracing::trace_span!("overlays").in_scope(|| self.stages.overlays.draw(&mut frame, rate));
+        if let Some(limit) = &mut self.stages.limit {
+            limit.draw(&mut frame);
+        }
+        tracing::trace_span!("crops").in_scope(|| {
+            for crop in &mut self.crops {
+                crop.send(&frame);
+            }
+        });
+        if self.stages.constant_rate.is_some() {
+            if let Some(older) = self.pending.replace(frame) {
+                self.recycle(older.data);
+            }
+            return;
+        }
+        let on_battery = self.control.on_battery();
+        let battery_ready = match &mut self.stages.battery {
+            Some(battery) if on_battery => battery.ready(frame.create_time),
+            _ => true,
+        };
+        if !self.stages.pacer.ready(frame.create_time) || !battery_ready {
+            self.recycle(frame.data);
+            return;
+        }
+        self.send_main(frame);
+    }
+
+    /// The rate frames of the main source leave with, set by `--cfr` or the lower of the
+    /// capture rate and `--max-fps`.
+    fn outgoing_rate(&self, frame: &OwnedFrame) -> Option<Fraction> {
+        match &self.stages.constant_rate {
+            Some(clock) => clock.rate(),
+            None => Some(self.stages.pacer.limit(frame.frame_rate())),
+        }
+    }
+
+    /// Sends a frame on the mirrors and the main source.
+    fn send_main(&mut self, mut frame: OwnedFrame) {
+        // Receivers are told the rate frames leave with, which is exact for NTSC-family rates.
+        let rate = self.outgoing_rate(&frame).and_then(ndi_rate);
+        if !self.stages.transform.is_identity() {
+            let _span = tracing::trace_span!("convert").entered();
+            let size = frame.format.size();
+            let (data, size) = self.stages.transform.apply(
+                &frame.data,
+                size.width,
+                size.height,
+                frame.stride as usize,
+            );
+            self.recycle(std::mem::replace(&mut frame.data, data));
+            frame.format.set_size(Rectangle {
+                width: size.width,
+                height: size.height,
+            });
+            frame.stride = size.width * 4;
+            // Regions and rotations are taken in buffer pixels, which are square from here on.
+            frame.display_aspect = None;
+        }
+        tracing::trace_span!("mirrors").in_scope(|| {
+            for mirror in &mut self.mirrors {
+                mirror.send(&frame, rate);
+            }
+        });
+
+        let battery_scale = match &self.stages.battery {
+            Some(battery) if self.control.on_battery() => battery.scale,
+            _ => 1,
+        };
+        let scale = match self.check_tally(frame.create_time) {
+            Quality::Full => battery_scale,
+            Quality::Reduced => REDUCED_SCALE.max(battery_scale),
+            Quality::Skip => {
+                self.recycle(frame.data);
+                return;
+            }
+        };
+        let mut size = frame.format.size();
+        let mut stride = frame.stride;
+        let data = if scale > 1 {
+            let _span = tracing::trace_span!("convert").entered();
+            let (reduced, width, height) = downscale(
+                &frame.data,
+                size.width,
+                size.height,
+                frame.stride as usize,
+                scale,
+            );
+            self.recycle(frame.data);
+            size = Rectangle { width, height };
+            stride = width * 4;
+            reduced
+        } else {
+            frame.data
+        };
+
+        let mut format = frame.format;
+        format.set_size(size);
+        let data = if self.stages.chroma == Chroma::Yuv422 && size.width >= 2 {
+            let _span = tracing::trace_span!("convert").entered();
+            let uyvy = to_uyvy(
+                &data,
+                size.width,
+                size.height,
+                stride as usize,
+                format.format(),
+            );
+            self.recycle(data);
+            format.set_format(VideoFormat::UYVY);
+            format.set_size(Rectangle {
+                width: size.width & !1,
+                height: size.height,
+            });
+            uyvy
+        } else {
+            data
+        };
+
+        // The SDK compresses this frame in the background while the next one is being copied
+        // into the buffer it hands back.
+        let size = format.size();
+        let bytes = data.len() as u64;
+        let sending = tracing::trace_span!("ndi_send").entered();
+        let released = self.sink.send_video_async(ndi::Frame {
+            width: size.width,
+            height: size.height,
+            format: ndi_format(format.format()),
+            data,
+            stride_in_bytes: ndi_stride(format.format(), size.width),
+            picture_aspect_ratio: frame.display_aspect,
+            frame_rate: rate,
+        });
+        drop(sending);
+        self.stats.frame_sent(
+            frame.create_time.elapsed(),
+            bytes,
+            size.width as u64 * size.height as u64,
+        );
+        // The pause screen needs the size and format of what was actually sent.
+        self.last_format = Some(format);
+        self.last_aspect = frame.display_aspect;
+        self.last_rate = rate;
+
+        if let Some(buffer) = released {
+            self.recycle(buffer);
+        }
+    }
+
+    /// Sends the frame the SDK still holds once more.
+    fn repeat_last(&mut self) {
+        let Some(format) = self.last_format else {
+            return;
+        };
+        // Repeats are held back off air like any other frame.
+        if self.check_tally(Instant::now()) == Quality::Skip {
+            return;
+        }
+        // Nothing is in flight after the pause screen, which sends synchronously.
+        let Some(data) = self.sink.flush_video() else {
+            return;
+        };
+
+        let size = format.size();
+        let released = self.sink.send_video_async(ndi::Frame {
+            width: size.width,
+            height: size.height,
+            format: ndi_format(format.format()),
+            data,
+            stride_in_bytes: ndi_stride(format.format(), size.width),
+            picture_aspect_ratio: self.last_aspect,
+            frame_rate: self.last_rate,
+        });
+        self.stats.frame_repeated();
+        if let Some(buffer) = released {
+            self.recycle(buffer);
+        }
+    }
+
+    /// Decides how a frame of the main source arriving at `now` is sent, and tells the health
+    /// check whether nothing is sent on purpose.
+    fn check_tally(&mut self, now: Instant) -> Quality {
+        let quality = self.stages.tally.check(&self.name, self.sink.tally(), now);
+        self.stats.set_off_air(self.stages.tally.is_stopped());
+        quality
+    }
+
+    pub fn send_audio(&mut self, chunk: AudioChunk) {
+        self.stages.audio.send(&mut self.sink, chunk);
+        self.stats.set_audio_levels(self.stages.audio.levels());
+        if self.stages.no_video {
+            self.stats.audio_only_sent();
+        }
+    }
+
+    fn recycle(&self, buffer: Vec<u8>) {
+        self.pool.try_send(buffer).ok();
+    }
+
+    /// Writes the frame for everyone who asked for a snapshot, off the NDI thread.
+    fn serve_snapshots(&self, frame: &OwnedFrame) {
+        let requests = self.control.take_snapshot_requests();
+        if requests.is_empty() {
+            return;
+        }
+
+        let image = Image::from_frame(frame.format, frame.stride, &frame.data);
+        std::thread::spawn(move || {
+            for request in requests {
+                let result = image.write_png(&request.path);
+                request.done.send(result).ok();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Instant, SystemTime};
+
+    use crossbeam_channel::Receiver;
+    use pipewire::spa::{
+        param::video::VideoFormat,
+        utils::{Fraction, Rectangle},
+    };
+
+    use super::*;
+    use crate::{
+        config::BatteryConfig,
+        crop::CropRegion,
+        latency::LatencySpec,
+        mirror::MirrorTarget,
+        pacing::parse_rate,
+        pip::PipLayout,
+        sink::mock::MockSink,
+        tally::TallySaving,
+        transform::{Rect, Rotation, Size},
+    };
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 4;
+
+    fn format(video_format: VideoFormat) -> VideoInfoRaw {
+        let mut format = VideoInfoRaw::new();
+        format.set_format(video_format);
+        format.set_size(Rectangle {
+            width: WIDTH,
+            height: HEIGHT,
+        });
+        format.set_framerate(Fraction { num: 30, denom: 1 });
+        format
+    }
+
+    /// A frame whose every byte is `fill`.
+    fn frame(fill: u8, age: Duration) -> OwnedFrame {
+        OwnedFrame {
+            format: format(VideoFormat::BGRx),
+            create_time: Instant::now() - age,
+            data: vec![fill; (WIDTH * HEIGHT * 4) as usize],
+            stride: WIDTH * 4,
+            cursor: None,
+            queued: None,
+            display_aspect: None,
+        }
+    }
+
+    struct Harness {
+        pipeline: Pipeline<MockSink>,
+        pool: Receiver<Vec<u8>>,
+        stats: Arc<Stats>,
+        control: Arc<Control>,
+    }
+
+    fn harness(pause_mode: PauseMode, crops: Vec<CropRegion>) -> Harness {
+        let (pool_tx, pool) = crossbeam_channel::unbounded();
+        let stats = Arc::new(Stats::default());
+        let control = Arc::new(Control::new(CaptureSource::Test));
+        let stages = Stages {
+            pip: None,
+            overlays: Overlays::default(),
+            pause_screen: PauseScreen::new(pause_mode, None),
+            audio: AudioOutput::new(0, 0.0, false),
+            dumper: None,
+            pacer: FramePacer::default(),
+            battery: None,
+            tally: TallyGate::new(TallySaving::Off),
+            transform: Transform::default(),
+            chroma: Chroma::default(),
+            latency: None,
+            limit: None,
+            remote_commands: Vec::new(),
+            constant_rate: None,
+            cover: None,
+            no_video: false,
+            throttle_capture: false,
+        };
+        let crops = crops
+            .into_iter()
+            .map(|region| {
+                let name = format!("Desktop - {}", region.name);
+                CropOutput::new(name, region, MockSink::default(), TallySaving::Off)
+            })
+            .collect();
+        let outputs = Outputs {
+            name: "Desktop".to_string(),
+            main: MockSink::default(),
+            crops,
+            mirrors: Vec::new(),
+        };
+        let pipeline = Pipeline::new(
+            outputs,
+            pool_tx,
+            stats.clone(),
+            control.clone(),
+            stages,
+            Duration::from_secs(3600),
+        );
+
+        Harness {
+            pipeline,
+            pool,
+            stats,
+            control,
+        }
+    }
+
+    #[test]
+    fn sends_fresh_frames() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.send_frame(frame(7, Duration::ZERO));
+
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].width, sent[0].height), (WIDTH, HEIGHT));
+        assert_eq!(sent[0].format, ndi::VideoFormat::BGRX);
+        assert_eq!(sent[0].stride, WIDTH * 4);
+        assert!(sent[0].asynchronous);
+        assert!(sent[0].data.iter().all(|&b| b == 7));
+        assert_eq!(h.stats.snapshot().sent, 1);
+        assert_eq!(h.stats.snapshot().sent_bytes, (WIDTH * HEIGHT * 4) as u64);
+    }
+
+    #[test]
+    fn maps_pixel_formats() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let mut rgba = frame(0, Duration::ZERO);
+        rgba.format = format(VideoFormat::RGBA);
+        h.pipeline.send_frame(rgba);
+
+        assert_eq!(h.pipeline.sink.video[0].format, ndi::VideoFormat::RGBA);
+    }
+
+    #[test]
+    fn drops_stale_frames() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.send_frame(frame(1, MAX_FRAME_AGE * 2));
+
+        assert!(h.pipeline.sink.video.is_empty());
+        assert_eq!(h.stats.snapshot().dropped, 1);
+        // The buffer goes straight back to the capture side.
+        assert_eq!(
+            h.pool.try_recv().unwrap().len(),
+            (WIDTH * HEIGHT * 4) as usize
+        );
+    }
+
+    #[test]
+    fn holds_frames_back_when_simulating_latency() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.latency =
+            Some(LatencySimulator::new("50".parse::<LatencySpec>().unwrap()));
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        h.pipeline.poll_delayed(Instant::now());
+        assert!(h.pipeline.sink.video.is_empty());
+
+        let due = h.pipeline.next_due().unwrap();
+        h.pipeline.poll_delayed(due);
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+        assert!(h.pipeline.next_due().is_none());
+    }
+
+    #[test]
+    fn repeats_last_frame_at_constant_rate() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.constant_rate = Some(FramePacer::new(Some(30)));
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+        assert!(h.pipeline.sink.video.is_empty());
+        // Only the latest frame is sent on a tick.
+        assert!(h.pool.try_recv().unwrap().iter().all(|&b| b == 1));
+
+        let start = Instant::now();
+        h.pipeline.poll_constant_rate(start);
+        let tick = h.pipeline.next_due().unwrap();
+        assert!(tick > start);
+        h.pipeline
+            .poll_constant_rate(tick - Duration::from_millis(1));
+        h.pipeline.poll_constant_rate(tick);
+
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|video| video.data.iter().all(|&b| b == 2)));
+        assert_eq!(h.stats.snapshot().sent, 1);
+        assert_eq!(h.stats.snapshot().repeated, 1);
+    }
+
+    #[test]
+    fn keeps_ntsc_rates_exact() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let rate = parse_rate("59.94").unwrap();
+        h.pipeline.stages.constant_rate = Some(FramePacer::at(Some(rate)));
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        let start = Instant::now();
+        h.pipeline.poll_constant_rate(start);
+        for _ in 1..6000 {
+            let tick = h.pipeline.next_due().unwrap();
+            h.pipeline.poll_constant_rate(tick);
+        }
+
+        // 6000 intervals at 60000/1001 take 100.1 s, to the nanosecond.
+        let end = h.pipeline.next_due().unwrap();
+        assert_eq!(end - start, Duration::from_millis(100_100));
+        assert_eq!(h.pipeline.sink.video[0].frame_rate, Some((60000, 1001)));
+    }
+
+    #[test]
+    fn sends_cover_without_video() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let red = Image {
+            width: 2,
+            height: 1,
+            rgba: [255, 0, 0, 255].repeat(2),
+        };
+        h.pipeline.stages.cover = Some(Cover::new(&red));
+        let start = h.pipeline.next_due().unwrap();
+        h.pipeline.poll_cover(start);
+        h.pipeline.poll_cover(start + Duration::from_millis(500));
+        let next = h.pipeline.next_due().unwrap();
+        assert_eq!(next - start, Duration::from_secs(1));
+        h.pipeline.poll_cover(next);
+
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 2);
+        assert_eq!((sent[0].width, sent[0].height), (2, 1));
+        assert_eq!(sent[0].format, ndi::VideoFormat::BGRX);
+        assert_eq!(sent[0].data, [0, 0, 255, 255].repeat(2));
+        assert!(h.stats.last_sent_age().is_some());
+    }
+
+    #[test]
+    fn subsamples_chroma_when_asked() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.chroma = Chroma::Yuv422;
+        let mut white = frame(255, Duration::ZERO);
+        white.format = format(VideoFormat::RGBx);
+        h.pipeline.send_frame(white);
+
+        let sent = &h.pipeline.sink.video[0];
+        assert_eq!(sent.format, ndi::VideoFormat::UYVY);
+        assert_eq!((sent.width, sent.stride), (WIDTH, WIDTH * 2));
+        assert_eq!(sent.data.len(), (WIDTH * HEIGHT * 2) as usize);
+        assert_eq!(sent.data[..4], [128, 235, 128, 235]);
+        let totals = h.stats.snapshot();
+        assert_eq!(totals.sent_bytes, (WIDTH * HEIGHT * 2) as u64);
+        assert_eq!(totals.sent_pixels, (WIDTH * HEIGHT) as u64);
+
+        // A frozen frame keeps the format it was sent in.
+        h.control.set_paused(true);
+        h.pipeline.poll_pause();
+        let frozen = &h.pipeline.sink.video[1];
+        assert_eq!(frozen.format, ndi::VideoFormat::UYVY);
+        assert_eq!(frozen.stride, WIDTH * 2);
+    }
+
+    #[test]
+    fn follows_allowed_remote_commands() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.remote_commands = vec![CommandKind::Pause];
+        let incoming = &mut h.pipeline.sink.incoming;
+        incoming.push_back(r#"<ndi_capabilities ntk_ptz="true"/>"#.to_string());
+        incoming.push_back(r#"<ndi_screenshare_command name="fps" value="5"/>"#.to_string());
+        incoming.push_back(r#"<ndi_screenshare_command name="pause"/>"#.to_string());
+        h.pipeline.poll_commands();
+
+        assert!(h.control.is_paused());
+        assert!(h.pipeline.poll_pause());
+
+        // The frame rate limit was not allowed to change, so once resumed every frame goes out.
+        h.control.set_paused(false);
+        assert!(!h.pipeline.poll_pause());
+        assert!(h.pipeline.stages.pacer.next_ready().is_none());
+        let sent = h.pipeline.sink.video.len();
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+        assert_eq!(h.pipeline.sink.video.len(), sent + 2);
+    }
+
+    #[test]
+    fn bounds_remote_frame_rates() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.remote_commands = vec![CommandKind::Fps];
+        h.pipeline.stages.constant_rate = Some(FramePacer::new(Some(30)));
+        let incoming = &mut h.pipeline.sink.incoming;
+        incoming.push_back(r#"<ndi_screenshare_command name="fps" value="4000000000"/>"#.into());
+        h.pipeline.poll_commands();
+        let clock = h.pipeline.stages.constant_rate.as_ref().unwrap();
+        assert_eq!(clock.rate(), Some(Fraction { num: 30, denom: 1 }));
+
+        let incoming = &mut h.pipeline.sink.incoming;
+        incoming.push_back(r#"<ndi_screenshare_command name="fps" value="29.97"/>"#.into());
+        h.pipeline.poll_commands();
+        let clock = h.pipeline.stages.constant_rate.as_ref().unwrap();
+        let ntsc = Fraction {
+            num: 30000,
+            denom: 1001,
+        };
+        assert_eq!(clock.rate(), Some(ntsc));
+    }
+
+    #[test]
+    fn publishes_source_label_once_changed() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.poll_source_label();
+        assert!(h.pipeline.sink.metadata.is_empty());
+
+        h.control.set_source_label("Slides & Notes".to_string());
+        h.pipeline.poll_source_label();
+        h.pipeline.poll_source_label();
+        let xml = r#"<ndi_screenshare_source label="Slides &amp; Notes"/>"#;
+        assert_eq!(h.pipeline.sink.metadata, vec![xml.to_string()]);
+        assert_eq!(h.pipeline.sink.connection_metadata.as_deref(), Some(xml));
+    }
+
+    #[test]
+    fn recreates_failed_sender() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.control.set_source_label("Slides".to_string());
+        h.pipeline.poll_source_label();
+        let now = Instant::now();
+        h.pipeline.poll_senders(now);
+        assert_eq!(h.pipeline.sink.recreated, 0);
+
+        h.pipeline.sink.failed = true;
+        h.pipeline.poll_senders(now + SENDER_CHECK_INTERVAL / 2);
+        assert_eq!(h.pipeline.sink.recreated, 0);
+        h.pipeline.poll_senders(now + SENDER_CHECK_INTERVAL);
+        assert_eq!(h.pipeline.sink.recreated, 1);
+
+        // Receivers of the new sender are told the label as well.
+        h.pipeline.poll_source_label();
+        let xml = r#"<ndi_screenshare_source label="Slides"/>"#;
+        assert_eq!(h.pipeline.sink.connection_metadata.as_deref(), Some(xml));
+    }
+
+    #[test]
+    fn idles_without_receivers_when_throttled() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        assert!(!h.pipeline.poll_receivers());
+        assert!(h.control.is_active());
+
+        h.pipeline.stages.throttle_capture = true;
+        h.pipeline.sink.connections = 1;
+        assert!(!h.pipeline.poll_receivers());
+        h.pipeline.sink.connections = 0;
+        assert!(h.pipeline.poll_receivers());
+        assert!(!h.control.is_active());
+    }
+
+    #[test]
+    fn reduces_quality_on_battery() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.battery = Some(BatteryProfile::new(&BatteryConfig {
+            enabled: true,
+            max_fps: 10,
+            scale: 2,
+        }));
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        h.control.set_on_battery(true);
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+        h.pipeline.send_frame(frame(3, Duration::ZERO));
+
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 2);
+        assert_eq!((sent[0].width, sent[0].height), (WIDTH, HEIGHT));
+        assert_eq!((sent[1].width, sent[1].height), (WIDTH / 2, HEIGHT / 2));
+        assert!(sent[1].data.iter().all(|&b| b == 2));
+    }
+
+    #[test]
+    fn recycles_buffers_released_by_the_sink() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        assert!(h.pool.try_recv().is_err());
+
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+        let released = h.pool.try_recv().unwrap();
+        assert!(released.iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn freezes_last_frame_while_paused() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.send_frame(frame(5, Duration::ZERO));
+
+        h.control.set_paused(true);
+        assert!(h.pipeline.poll_pause());
+        h.pipeline.discard(frame(9, Duration::ZERO));
+
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 2);
+        assert!(!sent[1].asynchronous);
+        assert!(sent[1].data.iter().all(|&b| b == 5));
+        // Frames captured while paused are neither sent nor counted as dropped.
+        assert_eq!(h.stats.snapshot().dropped, 0);
+        assert!(h.pool.try_recv().unwrap().iter().all(|&b| b == 9));
+
+        h.control.set_paused(false);
+        assert!(!h.pipeline.poll_pause());
+        // The frozen buffer is handed back on resume.
+        assert!(h.pool.try_recv().unwrap().iter().all(|&b| b == 5));
+    }
+
+    #[test]
+    fn sends_black_while_paused() {
+        let mut h = harness(PauseMode::Black, vec![]);
+        h.pipeline.send_frame(frame(5, Duration::ZERO));
+
+        h.control.set_paused(true);
+        h.pipeline.poll_pause();
+
+        let paused = h.pipeline.sink.video.last().unwrap();
+        assert_eq!((paused.width, paused.height), (WIDTH, HEIGHT));
+        assert!(paused
+            .data
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn keeps_pause_mode_after_max_duration() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.limit = Some(DurationLimit::new(Duration::ZERO, false));
+        h.pipeline.send_frame(frame(5, Duration::ZERO));
+        assert!(!h.pipeline.poll_limit());
+        h.pipeline.poll_pause();
+        let expired = h.pipeline.sink.video.last().unwrap();
+        assert!(expired.data.chunks_exact(4).all(|p| p == [0, 0, 0, 255]));
+
+        // Pausing by hand afterwards freezes the last frame again.
+        h.control.set_paused(false);
+        h.pipeline.poll_pause();
+        h.pipeline.send_frame(frame(6, Duration::ZERO));
+        h.control.set_paused(true);
+        h.pipeline.poll_pause();
+        let frozen = h.pipeline.sink.video.last().unwrap();
+        assert!(frozen.data.iter().all(|&b| b == 6));
+    }
+
+    #[test]
+    fn crops_regions() {
+        let region = CropRegion {
+            name: "corner".to_string(),
+            x: 6,
+            y: 2,
+            width: 4,
+            height: 4,
+            max_fps: None,
+            chroma: Chroma::Rgb444,
+        };
+        let mut h = harness(PauseMode::Freeze, vec![region]);
+        h.pipeline.send_frame(numbered_frame());
+
+        let crop = &h.pipeline.crops[0].sink().video[0];
+        // Clipped to the frame.
+        assert_eq!((crop.width, crop.height), (2, 2));
+        let firsts: Vec<u8> = crop.data.chunks_exact(4).map(|p| p[0]).collect();
+        let expected = |x: u32, y: u32| (y * WIDTH + x) as u8;
+        assert_eq!(
+            firsts,
+            [
+                expected(6, 2),
+                expected(7, 2),
+                expected(6, 3),
+                expected(7, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn crops_padded_rows() {
+        let region = CropRegion {
+            name: "middle".to_string(),
+            x: 2,
+            y: 1,
+            width: 2,
+            height: 2,
+            max_fps: None,
+            chroma: Chroma::Rgb444,
+        };
+        let mut h = harness(PauseMode::Freeze, vec![region]);
+        h.pipeline.send_frame(padded(numbered_frame(), 8));
+
+        let crop = &h.pipeline.crops[0].sink().video[0];
+        let firsts: Vec<u8> = crop.data.chunks_exact(4).map(|p| p[0]).collect();
+        let expected = |x: u32, y: u32| (y * WIDTH + x) as u8;
+        assert_eq!(
+            firsts,
+            [
+                expected(2, 1),
+                expected(3, 1),
+                expected(2, 2),
+                expected(3, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn paces_sources_independently() {
+        let region = CropRegion {
+            name: "all".to_string(),
+            x: 0,
+            y: 0,
+            width: WIDTH,
+            height: HEIGHT,
+            max_fps: None,
+            chroma: Chroma::Rgb444,
+        };
+        let mut h = harness(PauseMode::Freeze, vec![region]);
+        h.pipeline.stages.pacer = FramePacer::new(Some(1));
+
+        for fill in 0..3 {
+            h.pipeline.send_frame(frame(fill, Duration::ZERO));
+        }
+
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+        assert_eq!(h.pipeline.crops[0].sink().video.len(), 3);
+        // Skipped frames are neither sent nor dropped, and their buffers are reused.
+        assert_eq!(h.stats.snapshot().sent, 1);
+        assert_eq!(h.stats.snapshot().dropped, 0);
+        assert_eq!(h.pool.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn mirrors_processed_frames() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.mirrors = ["Full", "Proxy@studio/2", "Light:422"]
+            .into_iter()
+            .map(|target| {
+                let target: MirrorTarget = target.parse().unwrap();
+                MirrorOutput::new(target, MockSink::default(), TallySaving::Off)
+            })
+            .collect();
+
+        h.pipeline.send_frame(frame(42, Duration::ZERO));
+
+        let full = &h.pipeline.mirrors[0].sink().video[0];
+        assert_eq!((full.width, full.height), (WIDTH, HEIGHT));
+        assert!(full.data.iter().all(|&b| b == 42));
+        let proxy = &h.pipeline.mirrors[1].sink().video[0];
+        assert_eq!((proxy.width, proxy.height), (WIDTH / 2, HEIGHT / 2));
+        assert_eq!(proxy.data.len(), (WIDTH / 2 * HEIGHT / 2 * 4) as usize);
+        assert!(proxy.data.iter().all(|&b| b == 42));
+        let light = &h.pipeline.mirrors[2].sink().video[0];
+        assert_eq!(light.format, ndi::VideoFormat::UYVY);
+        assert_eq!((light.width, light.stride), (WIDTH, WIDTH * 2));
+        // Mirrors copy the frame, the main source still gets it.
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+    }
+
+    #[test]
+    fn mirrors_padded_rows() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.mirrors = ["Full", "Proxy/2"]
+            .into_iter()
+            .map(|target| {
+                let target: MirrorTarget = target.parse().unwrap();
+                MirrorOutput::new(target, MockSink::default(), TallySaving::Off)
+            })
+            .collect();
+
+        h.pipeline.send_frame(padded(frame(42, Duration::ZERO), 8));
+
+        let full = &h.pipeline.mirrors[0].sink().video[0];
+        assert_eq!(full.data.len(), (WIDTH * HEIGHT * 4) as usize);
+        assert!(full.data.iter().all(|&b| b == 42));
+        let proxy = &h.pipeline.mirrors[1].sink().video[0];
+        assert!(proxy.data.iter().all(|&b| b == 42));
+    }
+
+    /// A frame whose pixels hold their index in the first byte.
+    fn numbered_frame() -> OwnedFrame {
+        let mut frame = frame(0, Duration::ZERO);
+        for (i, pixel) in frame.data.chunks_exact_mut(4).enumerate() {
+            pixel[0] = i as u8;
+        }
+        frame
+    }
+
+    /// `frame` with `padding` bytes after every row, as GPU buffers often have.
+    fn padded(mut frame: OwnedFrame, padding: u32) -> OwnedFrame {
+        frame.data = frame
+            .data
+            .chunks(frame.stride as usize)
+            .flat_map(|row| [row, &vec![0xee; padding as usize]].concat())
+            .collect();
+        frame.stride += padding;
+        frame
+    }
+
+    #[test]
+    fn composites_inset_over_main_capture() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let (inset_tx, inset_rx) = crossbeam_channel::unbounded();
+        let (inset_pool_tx, inset_pool) = crossbeam_channel::unbounded();
+        let layout = "top-left,50,0".parse::<PipLayout>().unwrap();
+        h.pipeline.stages.pip = Some(PictureInPicture::new(layout, inset_rx, inset_pool_tx));
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        inset_tx.send(frame(200, Duration::ZERO)).unwrap();
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+
+        let sent = &h.pipeline.sink.video;
+        assert!(sent[0].data.iter().all(|&b| b == 1));
+        // Half the width, keeping the aspect ratio of the inset.
+        let row = (WIDTH * 4) as usize;
+        assert_eq!(sent[1].data[..16], [200, 200, 200, 255].repeat(4));
+        assert_eq!(sent[1].data[16..20], [2; 4]);
+        assert_eq!(sent[1].data[row..row + 4], [200, 200, 200, 255]);
+        assert_eq!(sent[1].data[row * 2..row * 2 + 4], [2; 4]);
+        assert!(inset_pool.try_recv().is_ok());
+    }
+
+    #[test]
+    fn skips_inset_with_margin_too_large() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let (inset_tx, inset_rx) = crossbeam_channel::unbounded();
+        let (inset_pool_tx, _inset_pool) = crossbeam_channel::unbounded();
+        let layout = "top-left,50,4294967295".parse::<PipLayout>().unwrap();
+        h.pipeline.stages.pip = Some(PictureInPicture::new(layout, inset_rx, inset_pool_tx));
+        inset_tx.send(frame(200, Duration::ZERO)).unwrap();
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+
+        assert!(h.pipeline.sink.video[0].data.iter().all(|&b| b == 2));
+    }
+
+    #[test]
+    fn reports_display_aspect_of_scaled_buffers() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        let mut scaled = frame(0, Duration::ZERO);
+        // An 8x4 buffer of a source that is 16:9 in logical pixels.
+        scaled.display_aspect = crate::frame::display_aspect(WIDTH, HEIGHT, Some((16, 9)));
+        h.pipeline.send_frame(scaled);
+        h.pipeline.send_frame(frame(0, Duration::ZERO));
+
+        assert_eq!(h.pipeline.sink.video[0].aspect, Some(16.0 / 9.0));
+        assert_eq!(h.pipeline.sink.video[1].aspect, None);
+    }
+
+    #[test]
+    fn rotates_main_source() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.transform.rotation = Rotation::Cw90;
+        h.pipeline.send_frame(numbered_frame());
+
+        let sent = &h.pipeline.sink.video[0];
+        assert_eq!((sent.width, sent.height), (HEIGHT, WIDTH));
+        assert_eq!(sent.stride, HEIGHT * 4);
+        let firsts: Vec<u8> = sent.data.chunks_exact(4).map(|p| p[0]).collect();
+        // The bottom-left corner ends up top-left, the top-left one top-right.
+        assert_eq!(firsts[0], ((HEIGHT - 1) * WIDTH) as u8);
+        assert_eq!(firsts[(HEIGHT - 1) as usize], 0);
+    }
+
+    #[test]
+    fn rotates_padded_rows() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.transform.rotation = Rotation::Cw90;
+        h.pipeline.send_frame(padded(numbered_frame(), 8));
+
+        let sent = &h.pipeline.sink.video[0];
+        assert_eq!(sent.stride, HEIGHT * 4);
+        let firsts: Vec<u8> = sent.data.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(firsts[0], ((HEIGHT - 1) * WIDTH) as u8);
+        assert_eq!(firsts[HEIGHT as usize], ((HEIGHT - 1) * WIDTH + 1) as u8);
+    }
+
+    #[test]
+    fn fits_region_on_portrait_canvas() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.transform.region = Some("2,0,4x4".parse::<Rect>().unwrap());
+        h.pipeline.stages.transform.canvas = Some("4x8".parse::<Size>().unwrap());
+        h.pipeline.send_frame(numbered_frame());
+
+        let sent = &h.pipeline.sink.video[0];
+        assert_eq!((sent.width, sent.height), (4, 8));
+        let firsts: Vec<u8> = sent.data.chunks_exact(4).map(|p| p[0]).collect();
+        // The square region is centered, with black bars above and below.
+        assert!(firsts[..8].iter().all(|&b| b == 0));
+        assert_eq!(&firsts[8..12], &[2, 3, 4, 5]);
+        assert_eq!(&firsts[20..24], &[26, 27, 28, 29]);
+        assert!(firsts[24..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn saves_bandwidth_while_off_air() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.tally = TallyGate::new(TallySaving::Reduce);
+
+        h.pipeline.send_frame(frame(10, Duration::ZERO));
+        h.pipeline.send_frame(frame(20, Duration::ZERO));
+        let sent = &h.pipeline.sink.video;
+        // Off air: half the size, and the second frame comes too soon for the reduced rate.
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].width, sent[0].height), (WIDTH / 2, HEIGHT / 2));
+        assert_eq!(sent[0].stride, WIDTH / 2 * 4);
+        assert!(sent[0].data.iter().all(|&b| b == 10));
+
+        h.pipeline.sink.tally.on_preview = true;
+        h.pipeline.send_frame(frame(30, Duration::ZERO));
+        let sent = &h.pipeline.sink.video;
+        assert_eq!(sent.len(), 2);
+        assert_eq!((sent[1].width, sent[1].height), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn stops_sending_while_off_air() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.tally = TallyGate::new(TallySaving::Stop);
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        assert!(h.pipeline.sink.video.is_empty());
+        assert!(h.stats.is_off_air());
+
+        h.pipeline.sink.tally.on_program = true;
+        h.pipeline.send_frame(frame(2, Duration::ZERO));
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+        assert!(!h.stats.is_off_air());
+    }
+
+    #[test]
+    fn stops_repeating_while_off_air() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.tally = TallyGate::new(TallySaving::Stop);
+        h.pipeline.stages.constant_rate = Some(FramePacer::new(Some(30)));
+        h.pipeline.sink.tally.on_program = true;
+        h.pipeline.send_frame(frame(1, Duration::ZERO));
+        h.pipeline.poll_constant_rate(Instant::now());
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+
+        h.pipeline.sink.tally.on_program = false;
+        let tick = h.pipeline.next_due().unwrap();
+        h.pipeline.poll_constant_rate(tick);
+        assert_eq!(h.pipeline.sink.video.len(), 1);
+        assert_eq!(h.stats.snapshot().repeated, 0);
+    }
+
+    #[test]
+    fn applies_audio_offset_and_gain() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.audio = AudioOutput::new(-40, 20.0, false);
+
+        let captured = SystemTime::now();
+        h.pipeline.send_audio(AudioChunk {
+            sample_rate: 48000,
+            channels: 2,
+            samples: vec![0.01, 0.02, -0.01, -0.02],
+            captured,
+        });
+
+        let sent = &h.pipeline.sink.audio[0];
+        assert_eq!(
+            (sent.sample_rate, sent.channels, sent.samples),
+            (48000, 2, 2)
+        );
+        let expected = captured
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as i64
+            / 100
+            - 40 * 10_000;
+        assert_eq!(sent.timecode, Some(expected));
+        for (sample, original) in sent.data.iter().zip([0.01, 0.02, -0.01, -0.02]) {
+            assert!((sample - original * 10.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_below_full_scale() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.stages.audio = AudioOutput::new(0, 6.0, true);
+        h.pipeline.send_audio(AudioChunk {
+            sample_rate: 48000,
+            channels: 1,
+            samples: vec![0.1, 0.9, -0.9],
+            captured: SystemTime::now(),
+        });
+
+        let sent = &h.pipeline.sink.audio[0].data;
+        assert!(sent.iter().all(|s| s.abs() < 1.0));
+        assert!(sent[1] > 0.8 && sent[2] < -0.8);
+    }
+
+    #[test]
+    fn meters_audio_levels() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        assert!(h.stats.audio_levels().is_none());
+
+        // One second of a half-scale square wave on the left channel, silence on the right.
+        let left = (0..48000).map(|i| if i % 2 == 0 { 0.5 } else { -0.5 });
+        h.pipeline.send_audio(AudioChunk {
+            sample_rate: 48000,
+            channels: 2,
+            samples: left.chain(std::iter::repeat(0.0).take(48000)).collect(),
+            captured: SystemTime::now(),
+        });
+
+        let levels = h.stats.audio_levels().unwrap();
+        assert!((levels.peak_db[0] + 6.02).abs() < 0.01);
+        assert!(levels.rms_db[0] < levels.peak_db[0] && levels.rms_db[0] > -7.0);
+        assert_eq!(levels.peak_db[1], crate::stats::MIN_LEVEL_DB);
+    }
+
+    #[test]
+    fn publishes_stats_as_metadata() {
+        let mut h = harness(PauseMode::Freeze, vec![]);
+        h.pipeline.reporter = Reporter::new(Duration::ZERO);
+        h.pipeline.sink.connections = 3;
+        h.pipeline.poll_stats();
+
+        assert!(h.pipeline.sink.metadata[0].starts_with("<ndi_screenshare_stats "));
+        assert_eq!(h.stats.connections(), 3);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{fmt, os::fd::OwnedFd};
+
+use anyhow::{anyhow, Result};
+use ashpd::{
+    desktop::{
+        screencast::{CursorMode, PersistMode, Screencast, SourceType},
+        ResponseError, Session,
+    },
+    WindowIdentifier,
+};
+
+/// A screencast started through the desktop portal, which keeps running while this is alive.
+pub struct PortalSession {
+    _proxy: Screencast<'static>,
+    _session: Session<'static>,
+    /// Every stream the user selected, in the order the portal listed them.
+    pub streams: Vec<PortalStream>,
+    /// Token to share the same source again later without asking the user.
+    pub restore_token: Option<String>,
+}
+
+/// A PipeWire stream shared by the portal.
+#[derive(Debug, Clone, Copy)]
+pub struct PortalStream {
+    pub node_id: u32,
+    pub source_type: Option<SourceType>,
+    /// Size of the source in the compositor's logical coordinates, which differs from the size of
+    /// the buffers under HiDPI or fractional scaling.
+    pub logical_size: Option<(u32, u32)>,
+    /// Position of a monitor in the compositor's logical coordinates.
+    pub position: Option<(i32, i32)>,
+}
+
+impl PortalStream {
+    /// What the portal tells about the source, e.g. `Monitor 1920x1080 at 0,0`.
+    pub fn label(&self) -> String {
+        let mut label = match self.source_type {
+            Some(SourceType::Monitor) => "Monitor",
+            Some(SourceType::Window) => "Window",
+            Some(SourceType::Virtual) => "Virtual output",
+            None => "Stream",
+        }
+        .to_string();
+        if let Some((width, height)) = self.logical_size {
+            label += &format!(" {}x{}", width, height);
+        }
+        if let Some((x, y)) = self.position {
+            label += &format!(" at {},{}", x, y);
+        }
+        label
+    }
+}
+
+impl fmt::Display for PortalStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (node {})", self.label(), self.node_id)
+    }
+}
+
+/// Exit status when sharing was cancelled or denied and not retried, so that service managers
+/// can tell it apart from failures.
+pub const DENIED_EXIT_CODE: i32 = 3;
+
+/// The user dismissed the screencast dialog, or the compositor refused to share.
+#[derive(Debug)]
+pub struct Denied {
+    pub cancelled: bool,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cancelled {
+            f.write_str("Screen sharing was cancelled")
+        } else {
+            f.write_str("Screen sharing was denied")
+        }
+    }
+}
+
+impl std::error::Error for Denied {}
+
+/// Asks the user what to share, unless `restore_token` is still valid, and returns the session
+/// along with the PipeWire remote to capture from. Only with `persist` is a token for the next
+/// session handed out.
+pub async fn start(
+    cursor_meta: bool,
+    virtual_output: bool,
+    restore_token: Option<&str>,
+    persist: bool,
+) -> Result<(PortalSession, OwnedFd)> {
+    let proxy = Screencast::new().await?;
+    let session = proxy.create_session().await?;
+    let source_types = if virtual_output {
+        if !proxy
+            .available_source_types()
+            .await?
+            .contains(SourceType::Virtual)
+        {
+            return Err(anyhow!(
+                "The screencast portal of this compositor cannot create virtual outputs"
+            ));
+        }
+        SourceType::Virtual.into()
+    } else {
+        SourceType::Monitor | SourceType::Window
+    };
+    proxy
+        .select_sources(
+            &session,
+            if cursor_meta {
+                CursorMode::Metadata
+            } else {
+                CursorMode::Embedded
+            },
+            source_types,
+            true,
+            restore_token,
+            if persist {
+                PersistMode::ExplicitlyRevoked
+            } else {
+                PersistMode::DoNot
+            },
+        )
+        .await?;
+    let response = proxy
+        .start(&session, &WindowIdentifier::default())
+        .await?
+        .response()
+        .map_err(|e| match e {
+            ashpd::Error::Response(e) => Denied {
+                cancelled: e == ResponseError::Cancelled,
+            }
+            .into(),
+            e => anyhow::Error::from(e),
+        })?;
+
+    let streams: Vec<PortalStream> = response
+        .streams()
+        .iter()
+        .map(|stream| PortalStream {
+            node_id: stream.pipe_wire_node_id(),
+            source_type: stream.source_type(),
+            logical_size: stream
+                .size()
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .map(|(width, height)| (width as u32, height as u32)),
+            position: stream.position(),
+        })
+        .collect();
+    if streams.is_empty() {
+        return Err(anyhow!("Nothing was selected for sharing"));
+    }
+    let fd = proxy.open_pipe_wire_remote(&session).await?;
+
+    let restore_token = response.restore_token().map(str::to_string);
+
+    Ok((
+        PortalSession {
+            _proxy: proxy,
+            _session: session,
+            streams,
+            restore_token,
+        },
+        fd,
+    ))
+}
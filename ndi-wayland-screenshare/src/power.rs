@@ -0,0 +1,58 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use ashpd::zbus;
+
+use crate::{config::BatteryConfig, control::Control, pacing::FramePacer};
+
+/// How often UPower is asked whether the machine runs on battery.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lower frame rate and resolution of the main source while running on battery.
+pub struct BatteryProfile {
+    pacer: FramePacer,
+    /// Both dimensions are divided by this.
+    pub scale: u32,
+}
+
+impl BatteryProfile {
+    pub fn new(config: &BatteryConfig) -> Self {
+        Self {
+            pacer: FramePacer::new(Some(config.max_fps)),
+            scale: config.scale.max(1),
+        }
+    }
+
+    /// Returns whether a frame arriving at `now` should be sent.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        self.pacer.ready(now)
+    }
+}
+
+/// Follows whether the machine runs on battery, as reported by UPower.
+pub async fn watch(control: Arc<Control>) -> Result<()> {
+    let connection = zbus::Connection::system().await?;
+    let upower = zbus::Proxy::new(
+        &connection,
+        "org.freedesktop.UPower",
+        "/org/freedesktop/UPower",
+        "org.freedesktop.UPower",
+    )
+    .await?;
+
+    loop {
+        let on_battery: bool = upower.get_property("OnBattery").await?;
+        if on_battery != control.on_battery() {
+            control.set_on_battery(on_battery);
+            if on_battery {
+                println!("Running on battery, reducing frame rate and resolution");
+            } else {
+                println!("Plugged in, back to full quality");
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
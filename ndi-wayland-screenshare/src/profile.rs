@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::{
+    span::{Attributes, Id, Record},
+    Event, Metadata, Subscriber,
+};
+
+/// One entry of the Chrome trace event format, which Perfetto, `chrome://tracing` and
+/// speedscope can all open.
+#[derive(Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    /// `B` when a span is entered, `E` when it is left.
+    ph: &'static str,
+    /// Microseconds since profiling started.
+    ts: f64,
+    pid: u32,
+    tid: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: &'a VecDeque<TraceEvent>,
+    display_time_unit: &'static str,
+}
+
+/// Events kept in memory, about 40 MB of them. Beyond that the oldest are dropped, so that a
+/// long session keeps its last minutes.
+const MAX_EVENTS: usize = 1 << 20;
+
+/// Records when the pipeline's tracing spans are entered and left.
+struct TraceRecorder {
+    start: Instant,
+    pid: u32,
+    next_id: AtomicU64,
+    names: Mutex<HashMap<u64, &'static str>>,
+    events: Arc<Mutex<Events>>,
+}
+
+#[derive(Default)]
+struct Events {
+    recorded: VecDeque<TraceEvent>,
+    dropped: u64,
+}
+
+impl TraceRecorder {
+    fn record(&self, id: &Id, ph: &'static str) {
+        let Some(&name) = self.names.lock().unwrap().get(&id.into_u64()) else {
+            return;
+        };
+        let event = TraceEvent {
+            name,
+            ph,
+            ts: self.start.elapsed().as_secs_f64() * 1e6,
+            pid: self.pid,
+            tid: unsafe { libc::gettid() },
+        };
+        let mut events = self.events.lock().unwrap();
+        if events.recorded.len() == MAX_EVENTS {
+            events.recorded.pop_front();
+            events.dropped += 1;
+        }
+        events.recorded.push_back(event);
+    }
+}
+
+impl Subscriber for TraceRecorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // Spans of the libraries, such as zbus, are not part of the pipeline.
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.names
+            .lock()
+            .unwrap()
+            .insert(id, span.metadata().name());
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        self.record(span, "B");
+    }
+
+    fn exit(&self, span: &Id) {
+        self.record(span, "E");
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        self.names.lock().unwrap().remove(&span.into_u64());
+        true
+    }
+}
+
+/// Collects per-stage timings for `--profile` and writes them out as a Chrome trace.
+pub struct Profiler {
+    path: PathBuf,
+    file: File,
+    events: Arc<Mutex<Events>>,
+}
+
+impl Profiler {
+    /// Starts recording the spans of every thread.
+    ///
+    /// The file is created right away, so that a bad path is caught before capturing starts.
+    pub fn install(path: PathBuf) -> Result<Self> {
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let events = Arc::new(Mutex::new(Events::default()));
+        let recorder = TraceRecorder {
+            start: Instant::now(),
+            pid: std::process::id(),
+            // Span IDs must not be zero.
+            next_id: AtomicU64::new(1),
+            names: Mutex::new(HashMap::new()),
+            events: events.clone(),
+        };
+        tracing::subscriber::set_global_default(recorder)
+            .context("Failed to install the profiler")?;
+
+        Ok(Self { path, file, events })
+    }
+
+    /// Writes what was recorded so far to the trace file.
+    pub fn finish(self) -> Result<()> {
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        let trace = Trace {
+            trace_events: &events.recorded,
+            display_time_unit: "ms",
+        };
+        serde_json::to_writer(BufWriter::new(self.file), &trace)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        println!(
+            "Wrote {} profile events to {}",
+            events.recorded.len(),
+            self.path.display()
+        );
+        if events.dropped > 0 {
+            println!("Dropped the {} oldest profile events", events.dropped);
+        }
+        Ok(())
+    }
+}
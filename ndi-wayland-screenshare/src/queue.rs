@@ -0,0 +1,96 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{frame::OwnedFrame, stats::Stats};
+
+/// What to do with a captured frame when queued frames already use up the memory budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DropPolicy {
+    /// Discard the oldest queued frames to make room.
+    DropOldest,
+    /// Discard the new frame.
+    DropNewest,
+    /// Wait a little for the NDI thread to catch up, then discard the new frame.
+    Block,
+}
+
+/// How long [`DropPolicy::Block`] waits for room before giving up on a frame.
+const BLOCK_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Queue of captured frames waiting for the NDI thread, keeping the memory they hold under a
+/// budget so that a stalled sender cannot take the whole machine down with it.
+#[derive(Clone)]
+pub struct FrameQueue {
+    tx: Sender<OwnedFrame>,
+    /// Used to discard the oldest frames.
+    rx: Receiver<OwnedFrame>,
+    stats: Arc<Stats>,
+    budget: u64,
+    policy: DropPolicy,
+}
+
+impl FrameQueue {
+    pub fn new(budget: u64, policy: DropPolicy, stats: Arc<Stats>) -> (Self, Receiver<OwnedFrame>) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let queue = Self {
+            tx,
+            rx: rx.clone(),
+            stats,
+            budget,
+            policy,
+        };
+        (queue, rx)
+    }
+
+    pub fn push(&self, mut frame: OwnedFrame) {
+        let bytes = frame.data.capacity() as u64;
+        let started = Instant::now();
+
+        self.stats
+            .set_queue_full(self.stats.queued_bytes() + bytes > self.budget);
+        while self.stats.queued_bytes() + bytes > self.budget {
+            match self.policy {
+                DropPolicy::DropOldest => match self.rx.try_recv() {
+                    Ok(_) => self.stats.frame_dropped(),
+                    // Only frames being sent are left, never starve the receiver.
+                    Err(_) => break,
+                },
+                DropPolicy::DropNewest => {
+                    self.stats.frame_dropped();
+                    return;
+                }
+                DropPolicy::Block => {
+                    if started.elapsed() >= BLOCK_TIMEOUT {
+                        self.stats.frame_dropped();
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+
+        self.stats.frame_queued(bytes);
+        frame.queued = Some(QueueTicket {
+            stats: self.stats.clone(),
+            bytes,
+        });
+        self.tx.send(frame).ok();
+    }
+}
+
+/// Keeps a frame counted in the queue memory until it is dropped.
+pub struct QueueTicket {
+    stats: Arc<Stats>,
+    bytes: u64,
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.stats.frame_dequeued(self.bytes);
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+use pipewire::spa::utils::Fraction;
+
+use crate::{pacing::parse_rate, pause::PauseMode};
+
+/// Name of the metadata element carrying commands.
+const ELEMENT: &str = "<ndi_screenshare_command";
+
+/// Kinds of commands receivers can be allowed to send, see `--remote-commands`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CommandKind {
+    Pause,
+    Resume,
+    Fps,
+    SelectSource,
+}
+
+/// A command sent by a receiver as metadata, e.g.
+/// `<ndi_screenshare_command name="fps" value="15"/>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Pauses, showing what `value` names if given.
+    Pause(Option<PauseMode>),
+    Resume,
+    /// Limits the frame rate of the main source, lifting the limit for `0`.
+    Fps(Option<Fraction>),
+    /// Asks the user to pick another source through the portal.
+    SelectSource,
+}
+
+impl Command {
+    /// Parses a metadata frame, returning `None` if it is not a command at all.
+    pub fn parse(xml: &str) -> Option<Result<Self>> {
+        let start = xml.find(ELEMENT)? + ELEMENT.len();
+        let element = &xml[start..];
+        let element = &element[..element.find('>').unwrap_or(element.len())];
+        Some(Self::from_attributes(element))
+    }
+
+    fn from_attributes(element: &str) -> Result<Self> {
+        let name = attribute(element, "name").ok_or_else(|| anyhow!("Command without a name"))?;
+        let value = attribute(element, "value");
+        let kind =
+            CommandKind::from_str(name, true).map_err(|_| anyhow!("Unknown command: {}", name))?;
+
+        Ok(match kind {
+            CommandKind::Pause => Self::Pause(
+                value
+                    .map(|v| PauseMode::from_str(v, true).map_err(|e| anyhow!(e)))
+                    .transpose()?,
+            ),
+            CommandKind::Resume => Self::Resume,
+            CommandKind::Fps => {
+                let value = value.ok_or_else(|| anyhow!("fps needs a value"))?;
+                if value.trim() == "0" {
+                    Self::Fps(None)
+                } else {
+                    Self::Fps(Some(parse_rate(value)?))
+                }
+            }
+            CommandKind::SelectSource => Self::SelectSource,
+        })
+    }
+
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Self::Pause(_) => CommandKind::Pause,
+            Self::Resume => CommandKind::Resume,
+            Self::Fps(_) => CommandKind::Fps,
+            Self::SelectSource => CommandKind::SelectSource,
+        }
+    }
+}
+
+/// Value of the attribute `key` in the body of an element, which is short enough that neither
+/// entities nor single quotes are worth supporting.
+fn attribute<'a>(element: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!(" {}=\"", key);
+    let start = element.find(&pattern)? + pattern.len();
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
@@ -0,0 +1,189 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{args::CaptureSource, control::Control, names, stats::Stats};
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+
+/// Longest request line accepted, clients sending more are disconnected.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// `$XDG_RUNTIME_DIR/ndi-screenshare.sock`, if the runtime directory is known. Instances
+/// publishing under another `name` than the default get a socket of their own, such as
+/// `ndi-screenshare-Desktop_2.sock`.
+pub fn default_socket_path(name: Option<&str>) -> Option<PathBuf> {
+    let file_name = match name {
+        Some(name) => format!("ndi-screenshare-{}.sock", names::file_name(name)),
+        None => "ndi-screenshare.sock".to_string(),
+    };
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join(file_name))
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Creates the control socket at `path`, ready to be handed to the runtime.
+pub fn bind(path: &Path) -> Result<std::os::unix::net::UnixListener> {
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(anyhow!(
+            "Another instance is listening on {}, see --control-socket",
+            path.display()
+        ));
+    }
+    // A previous instance that was killed leaves its socket behind.
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    println!("Listening for control commands on {}", path.display());
+    Ok(listener)
+}
+
+/// Serves newline-delimited JSON-RPC 2.0 requests, e.g.
+/// `echo '{"jsonrpc":"2.0","id":1,"method":"pause"}' | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/ndi-screenshare.sock`.
+///
+/// Any client connecting wakes up an idle share.
+pub async fn serve(listener: UnixListener, control: Arc<Control>, stats: Arc<Stats>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        if !control.is_active() {
+            println!("Control client connected, starting capture");
+            control.activate();
+        }
+        let (control, stats) = (control.clone(), stats.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, &control, &stats).await {
+                eprintln!("Control client error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: UnixStream, control: &Control, stats: &Stats) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = (&mut reader)
+            .take(MAX_REQUEST_SIZE as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if read == 0 {
+            break;
+        }
+        if line.len() > MAX_REQUEST_SIZE {
+            return Err(anyhow!("Request longer than {} bytes", MAX_REQUEST_SIZE));
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let response = match serde_json::from_slice::<Request>(&line) {
+            Ok(request) => match call(&request.method, &request.params, control, stats).await {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": request.id,
+                    "error": { "code": code, "message": message },
+                }),
+            },
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": e.to_string() },
+            }),
+        };
+
+        let mut response = serde_json::to_vec(&response)?;
+        response.push(b'\n');
+        write.write_all(&response).await?;
+    }
+
+    Ok(())
+}
+
+async fn call(
+    method: &str,
+    params: &Value,
+    control: &Control,
+    stats: &Stats,
+) -> Result<Value, (i32, String)> {
+    match method {
+        "status" => Ok(status(control)),
+        "pause" => {
+            control.set_paused(true);
+            Ok(status(control))
+        }
+        "resume" => {
+            control.set_paused(false);
+            Ok(status(control))
+        }
+        "toggle_pause" => {
+            control.toggle_pause();
+            Ok(status(control))
+        }
+        "stats" => Ok(json!({
+            "totals": stats.snapshot(),
+            "last": stats.last_report(),
+            "audio": stats.audio_levels(),
+        })),
+        "select_source" => {
+            if control.source() != CaptureSource::Portal {
+                return Err((
+                    SERVER_ERROR,
+                    "Only portal captures can switch sources".to_string(),
+                ));
+            }
+            control.request_source_switch();
+            Ok(Value::Null)
+        }
+        "snapshot" => {
+            let Some(path) = params.get("path").and_then(Value::as_str) else {
+                return Err((INVALID_PARAMS, "Expected a \"path\" parameter".to_string()));
+            };
+            control
+                .snapshot(path.into())
+                .await
+                .map_err(|e| (SERVER_ERROR, e.to_string()))?;
+            Ok(json!({ "path": path }))
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+fn status(control: &Control) -> Value {
+    let source = match control.source() {
+        CaptureSource::Portal => "portal",
+        CaptureSource::Test => "test",
+    };
+    json!({
+        "active": control.is_active(),
+        "paused": control.is_paused(),
+        "source": source,
+    })
+}
@@ -0,0 +1,235 @@
+use std::{
+    ffi::CString,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+// Landlock ABI from linux/landlock.h, which libc does not have yet.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Every right of the first Landlock ABI, up to `LANDLOCK_ACCESS_FS_MAKE_SYM`.
+const ACCESS_FS_ABI_1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Architectures the seccomp filter knows the syscall numbers of.
+const SECCOMP_SUPPORTED: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscalls failing with `EPERM` once hardened.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const DENIED_SYSCALLS: [libc::c_long; 3] = [libc::SYS_execve, libc::SYS_execveat, libc::SYS_ptrace];
+
+/// Restricts the process for the rest of its life: no programs can be executed, and nothing can
+/// be written to the filesystem except below `writable` and to `/dev/null`.
+///
+/// Landlock only confines the calling thread and the threads it starts afterwards, so this has to
+/// run before the async runtime and the capture and send threads are started. Missing writable
+/// directories are created.
+pub fn harden(writable: &[PathBuf]) -> Result<()> {
+    // Checked first, so that nothing is restricted halfway.
+    if !SECCOMP_SUPPORTED {
+        return Err(anyhow!(
+            "--harden is only supported on x86_64 and aarch64, not {}",
+            std::env::consts::ARCH
+        ));
+    }
+    for dir in writable {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    // Required to install a seccomp filter or Landlock ruleset without privileges, and a good
+    // idea anyway.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set no_new_privs");
+    }
+
+    restrict_filesystem(writable)?;
+    deny_syscalls()?;
+    Ok(())
+}
+
+fn restrict_filesystem(writable: &[PathBuf]) -> Result<()> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<LandlockRulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Err(anyhow!(
+            "Landlock is not available, it needs Linux 5.13 or later with Landlock enabled"
+        ));
+    }
+
+    let mut handled = ACCESS_FS_ABI_1;
+    if abi >= 2 {
+        handled |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+    // Reading stays allowed, PipeWire and the NDI SDK load plugins and configuration lazily.
+    handled &= !(ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR);
+
+    let attr = LandlockRulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const LandlockRulesetAttr,
+            std::mem::size_of::<LandlockRulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create Landlock ruleset");
+    }
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+
+    for dir in writable {
+        allow_beneath(&ruleset, dir, handled & !ACCESS_FS_EXECUTE)?;
+    }
+    allow_beneath(
+        &ruleset,
+        Path::new("/dev/null"),
+        handled & (ACCESS_FS_WRITE_FILE | ACCESS_FS_TRUNCATE),
+    )?;
+
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to enforce Landlock ruleset");
+    }
+    Ok(())
+}
+
+fn allow_beneath(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to open {}", path.display()));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let attr = LandlockPathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd.as_raw_fd(),
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const LandlockPathBeneathAttr,
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to allow writing to {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Installs a seccomp filter on every thread of the process.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn deny_syscalls() -> Result<()> {
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+    let errno = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    let mut filter = vec![
+        // Syscall numbers differ between architectures, so anything else is killed.
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH,
+            1,
+            0,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        // x32 syscalls share the architecture but not the numbers.
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            0x4000_0000,
+            0,
+            1,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+    for nr in DENIED_SYSCALLS {
+        filter.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            nr as u32,
+            0,
+            1,
+        ));
+        filter.push(stmt(libc::BPF_RET | libc::BPF_K, errno));
+    }
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &program as *const libc::sock_fprog,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to install seccomp filter");
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn deny_syscalls() -> Result<()> {
+    Err(anyhow!("No seccomp filter for {}", std::env::consts::ARCH))
+}
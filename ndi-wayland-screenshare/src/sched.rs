@@ -0,0 +1,85 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RtPolicy {
+    Fifo,
+    Rr,
+}
+
+/// A set of CPUs, written like `2,3` or `4-7`.
+#[derive(Debug, Clone)]
+pub struct CpuList(pub Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in s.split(',') {
+            let cpu = |v: &str| {
+                v.trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid CPU list: {}", s))
+            };
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (cpu(first)?, cpu(last)?),
+                None => (cpu(part)?, cpu(part)?),
+            };
+            if first > last || last >= libc::CPU_SETSIZE as usize {
+                return Err(anyhow!("Invalid CPU list: {}", s));
+            }
+            cpus.extend(first..=last);
+        }
+        Ok(Self(cpus))
+    }
+}
+
+/// Scheduling settings for the threads that capture and send frames.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadScheduling {
+    pub realtime: Option<(RtPolicy, i32)>,
+    pub cpus: Option<CpuList>,
+}
+
+impl ThreadScheduling {
+    /// Applies the settings to the calling thread, only warning about what is not permitted.
+    pub fn apply(&self, thread: &str) {
+        if let Some((policy, priority)) = self.realtime {
+            let policy = match policy {
+                RtPolicy::Fifo => libc::SCHED_FIFO,
+                RtPolicy::Rr => libc::SCHED_RR,
+            };
+            let param = libc::sched_param {
+                sched_priority: priority,
+            };
+            let err = unsafe { libc::pthread_setschedparam(libc::pthread_self(), policy, &param) };
+            if err != 0 {
+                eprintln!(
+                    "Cannot use realtime scheduling for the {} thread: {}",
+                    thread,
+                    std::io::Error::from_raw_os_error(err)
+                );
+            }
+        }
+
+        if let Some(CpuList(cpus)) = &self.cpus {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in cpus {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            let result =
+                unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+            if result != 0 {
+                eprintln!(
+                    "Cannot pin the {} thread to CPUs {:?}: {}",
+                    thread,
+                    cpus,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
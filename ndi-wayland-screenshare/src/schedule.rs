@@ -0,0 +1,169 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::{clock::LocalTime, control::Control};
+
+/// How often the schedule is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Days of the week, in the order of `tm_wday`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Sun,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+}
+
+const EVERY_DAY: [Weekday; 7] = [
+    Weekday::Sun,
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+];
+
+/// A local time of day, written as `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight.
+    minutes: u32,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let error = || anyhow!("Time must be HH:MM: {}", s);
+        let (hours, minutes) = s.split_once(':').ok_or_else(error)?;
+        let hours: u32 = hours.parse().map_err(|_| error())?;
+        let minutes: u32 = minutes.parse().map_err(|_| error())?;
+        if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+            return Err(error());
+        }
+        Ok(Self {
+            minutes: hours * 60 + minutes,
+        })
+    }
+}
+
+/// A stretch of time to stream during, on the given days. A range ending before it starts runs
+/// past midnight into the next day.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    #[serde(default = "every_day")]
+    pub days: Vec<Weekday>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+fn every_day() -> Vec<Weekday> {
+    EVERY_DAY.to_vec()
+}
+
+impl ScheduleEntry {
+    fn contains(&self, now: &LocalTime) -> bool {
+        let (start, end) = (self.start.minutes, self.end.minutes);
+        let minutes = now.hour * 60 + now.minute;
+        let on = |day: u32| self.days.contains(&EVERY_DAY[day as usize % 7]);
+        if start <= end {
+            on(now.weekday) && (start..end).contains(&minutes)
+        } else {
+            // The part after midnight belongs to the day the range started on.
+            (on(now.weekday) && minutes >= start) || (on(now.weekday + 6) && minutes < end)
+        }
+    }
+}
+
+/// Pauses streaming outside of the scheduled times and resumes it within them.
+///
+/// Only the transitions are acted on, so pausing or resuming by hand holds until the next one.
+pub async fn run(entries: Vec<ScheduleEntry>, control: Arc<Control>) {
+    let mut on_air = None;
+    loop {
+        let now = LocalTime::at(SystemTime::now());
+        let scheduled = entries.iter().any(|entry| entry.contains(&now));
+        if on_air != Some(scheduled) {
+            control.set_paused(!scheduled);
+            if scheduled {
+                println!("Scheduled streaming started");
+            } else {
+                println!("Outside of the scheduled times, paused");
+            }
+            on_air = Some(scheduled);
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> TimeOfDay {
+        TimeOfDay::try_from(s.to_string()).unwrap()
+    }
+
+    fn at(weekday: Weekday, hour: u32, minute: u32) -> LocalTime {
+        LocalTime {
+            weekday: weekday as u32,
+            hour,
+            minute,
+            second: 0,
+            millis: 0,
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(time("00:00").minutes, 0);
+        assert_eq!(time("9:05").minutes, 9 * 60 + 5);
+        assert_eq!(time("24:00").minutes, 24 * 60);
+        for s in ["24:01", "25:00", "12:60", "12", "12:", "noon"] {
+            assert!(TimeOfDay::try_from(s.to_string()).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn runs_until_end_of_day() {
+        let entry = ScheduleEntry {
+            days: vec![Weekday::Mon],
+            start: time("18:00"),
+            end: time("24:00"),
+        };
+        assert!(!entry.contains(&at(Weekday::Mon, 17, 59)));
+        assert!(entry.contains(&at(Weekday::Mon, 18, 0)));
+        assert!(entry.contains(&at(Weekday::Mon, 23, 59)));
+        assert!(!entry.contains(&at(Weekday::Tue, 0, 0)));
+    }
+
+    #[test]
+    fn runs_past_midnight_into_the_next_week() {
+        let entry = ScheduleEntry {
+            days: vec![Weekday::Sat],
+            start: time("22:00"),
+            end: time("02:00"),
+        };
+        assert!(!entry.contains(&at(Weekday::Sat, 21, 59)));
+        assert!(entry.contains(&at(Weekday::Sat, 23, 0)));
+        // Sunday morning belongs to the Saturday night the range started on.
+        assert!(entry.contains(&at(Weekday::Sun, 1, 59)));
+        assert!(!entry.contains(&at(Weekday::Sun, 2, 0)));
+        assert!(!entry.contains(&at(Weekday::Sun, 23, 0)));
+        // Saturday morning would belong to Friday, which is not scheduled.
+        assert!(!entry.contains(&at(Weekday::Sat, 1, 0)));
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+/// Destination of everything the NDI thread produces, implemented by [`ndi::Sender`] and by a
+/// mock in tests.
+pub trait FrameSink {
+    /// Schedules a frame for sending, returning the buffer of the previous asynchronous frame.
+    fn send_video_async(&mut self, frame: ndi::Frame<Vec<u8>>) -> Option<Vec<u8>>;
+
+    /// Sends a frame, returning once it is no longer used.
+    fn send_video(&mut self, frame: ndi::Frame<&mut [u8]>);
+
+    /// Waits for the in-flight asynchronous frame to be done and returns its buffer.
+    fn flush_video(&mut self) -> Option<Vec<u8>>;
+
+    fn send_audio(&mut self, frame: &ndi::AudioFrame);
+
+    fn send_metadata(&mut self, data: &str);
+
+    /// Replaces the metadata receivers are sent when they connect.
+    fn set_connection_metadata(&mut self, data: &str);
+
+    /// Returns the next metadata frame sent by a receiver, if one is waiting.
+    fn receive_metadata(&mut self) -> Option<String>;
+
+    fn connections(&self) -> u32;
+
+    fn tally(&self) -> ndi::Tally;
+
+    /// Whether the SDK still considers the sender usable.
+    fn is_healthy(&self) -> bool;
+
+    /// Creates the sender again under the same name, after it stopped working.
+    fn recreate(&mut self) -> anyhow::Result<()>;
+}
+
+/// Creates `sink` again if it stopped working, returning whether it was.
+pub fn recover(sink: &mut impl FrameSink, name: &str) -> bool {
+    if sink.is_healthy() {
+        return false;
+    }
+    eprintln!("NDI sender \"{}\" stopped working, creating it again", name);
+    match sink.recreate() {
+        Ok(()) => {
+            println!("NDI sender \"{}\" recreated", name);
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to recreate NDI sender \"{}\": {}", name, e);
+            false
+        }
+    }
+}
+
+impl FrameSink for ndi::Sender {
+    fn send_video_async(&mut self, frame: ndi::Frame<Vec<u8>>) -> Option<Vec<u8>> {
+        self.send_async(frame)
+    }
+
+    fn send_video(&mut self, frame: ndi::Frame<&mut [u8]>) {
+        self.send(frame);
+    }
+
+    fn flush_video(&mut self) -> Option<Vec<u8>> {
+        self.flush_async()
+    }
+
+    fn send_audio(&mut self, frame: &ndi::AudioFrame) {
+        ndi::Sender::send_audio(self, frame);
+    }
+
+    fn send_metadata(&mut self, data: &str) {
+        ndi::Sender::send_metadata(self, data);
+    }
+
+    fn set_connection_metadata(&mut self, data: &str) {
+        ndi::Sender::set_connection_metadata(self, data);
+    }
+
+    fn receive_metadata(&mut self) -> Option<String> {
+        ndi::Sender::receive_metadata(self, Duration::ZERO)
+    }
+
+    fn connections(&self) -> u32 {
+        self.connections_count()
+    }
+
+    fn tally(&self) -> ndi::Tally {
+        ndi::Sender::tally(self)
+    }
+
+    fn is_healthy(&self) -> bool {
+        ndi::Sender::is_healthy(self)
+    }
+
+    fn recreate(&mut self) -> anyhow::Result<()> {
+        ndi::Sender::recreate(self)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::collections::VecDeque;
+
+    use super::FrameSink;
+
+    pub struct SentVideo {
+        pub width: u32,
+        pub height: u32,
+        pub format: ndi::VideoFormat,
+        pub stride: u32,
+        pub data: Vec<u8>,
+        pub aspect: Option<f32>,
+        pub frame_rate: Option<(u32, u32)>,
+        pub asynchronous: bool,
+    }
+
+    pub struct SentAudio {
+        pub sample_rate: u32,
+        pub channels: u32,
+        pub samples: u32,
+        pub timecode: Option<i64>,
+        pub data: Vec<f32>,
+    }
+
+    /// Records what is sent and hands buffers back like the SDK does.
+    #[derive(Default)]
+    pub struct MockSink {
+        pub video: Vec<SentVideo>,
+        pub audio: Vec<SentAudio>,
+        pub metadata: Vec<String>,
+        pub connection_metadata: Option<String>,
+        /// Metadata waiting to be received from receivers.
+        pub incoming: VecDeque<String>,
+        pub in_flight: Option<Vec<u8>>,
+        pub connections: u32,
+        pub tally: ndi::Tally,
+        pub failed: bool,
+        pub recreated: u32,
+    }
+
+    impl FrameSink for MockSink {
+        fn send_video_async(&mut self, frame: ndi::Frame<Vec<u8>>) -> Option<Vec<u8>> {
+            self.video.push(SentVideo {
+                width: frame.width,
+                height: frame.height,
+                format: frame.format,
+                stride: frame.stride_in_bytes,
+                data: frame.data.clone(),
+                aspect: frame.picture_aspect_ratio,
+                frame_rate: frame.frame_rate,
+                asynchronous: true,
+            });
+            self.in_flight.replace(frame.data)
+        }
+
+        fn send_video(&mut self, frame: ndi::Frame<&mut [u8]>) {
+            self.video.push(SentVideo {
+                width: frame.width,
+                height: frame.height,
+                format: frame.format,
+                stride: frame.stride_in_bytes,
+                data: frame.data.to_vec(),
+                aspect: frame.picture_aspect_ratio,
+                frame_rate: frame.frame_rate,
+                asynchronous: false,
+            });
+        }
+
+        fn flush_video(&mut self) -> Option<Vec<u8>> {
+            self.in_flight.take()
+        }
+
+        fn send_audio(&mut self, frame: &ndi::AudioFrame) {
+            self.audio.push(SentAudio {
+                sample_rate: frame.sample_rate,
+                channels: frame.channels,
+                samples: frame.samples,
+                timecode: frame.timecode,
+                data: frame.data.to_vec(),
+            });
+        }
+
+        fn send_metadata(&mut self, data: &str) {
+            self.metadata.push(data.to_string());
+        }
+
+        fn set_connection_metadata(&mut self, data: &str) {
+            self.connection_metadata = Some(data.to_string());
+        }
+
+        fn receive_metadata(&mut self) -> Option<String> {
+            self.incoming.pop_front()
+        }
+
+        fn connections(&self) -> u32 {
+            self.connections
+        }
+
+        fn tally(&self) -> ndi::Tally {
+            self.tally
+        }
+
+        fn is_healthy(&self) -> bool {
+            !self.failed
+        }
+
+        fn recreate(&mut self) -> anyhow::Result<()> {
+            self.failed = false;
+            self.recreated += 1;
+            self.connection_metadata = None;
+            Ok(())
+        }
+    }
+}
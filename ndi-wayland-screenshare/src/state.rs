@@ -0,0 +1,76 @@
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// What is needed to bring a share back after the process was killed or crashed, saved to
+/// `$XDG_STATE_HOME/ndi-wayland-screenshare/session.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    /// Command line the session was started with, without `--resume`.
+    pub argv: Vec<String>,
+    /// Token letting the portal share the same source again without asking.
+    pub restore_token: Option<String>,
+    /// PipeWire node of the shared source, for reference only as node ids change between
+    /// sessions.
+    pub node_id: Option<u32>,
+    /// Name the sender was published under, which differs from the default after a conflict.
+    pub sender_name: String,
+    /// What the shared source was last called, see `--name-from-source`.
+    pub label: Option<String>,
+}
+
+impl SessionState {
+    pub fn new() -> Self {
+        Self {
+            argv: std::env::args().filter(|arg| arg != "--resume").collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = state_path().ok_or_else(|| anyhow!("Cannot locate the state directory"))?;
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("No session to resume in {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = state_path().ok_or_else(|| anyhow!("Cannot locate the state directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Written next to the old file and renamed over it, so a crash never leaves half a file.
+        // Only readable by the user, as the restore token grants sharing the screen.
+        let tmp = path.with_extension("toml.tmp");
+        // A file left over by a crash would keep its permissions otherwise.
+        std::fs::remove_file(&tmp).ok();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        drop(file);
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Directory the session state is saved in.
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+    Some(dir.join("ndi-wayland-screenshare"))
+}
+
+fn state_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("session.toml"))
+}
@@ -0,0 +1,359 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// Pipeline counters shared between the capture and the NDI thread.
+#[derive(Default)]
+pub struct Stats {
+    captured: AtomicU64,
+    dropped: AtomicU64,
+    /// Buffers PipeWire flagged as corrupted, which are never sent.
+    corrupted: AtomicU64,
+    /// Frames sent once more by the constant frame rate, not counted in `sent`.
+    repeated: AtomicU64,
+    sent: AtomicU64,
+    sent_bytes: AtomicU64,
+    sent_pixels: AtomicU64,
+    latency_total_us: AtomicU64,
+    queued_bytes: AtomicU64,
+    /// Frames currently waiting in the queue, and the most there ever were.
+    queued_frames: AtomicU64,
+    queue_high_watermark: AtomicU64,
+    /// Time the queue spent at its memory budget, not counting the current stretch.
+    queue_full_us: AtomicU64,
+    queue_full_since: Mutex<Option<Instant>>,
+    /// Whether the NDI thread is working on a frame, including waiting for the SDK to finish
+    /// the previous one.
+    sending: AtomicBool,
+    /// Whether the main source sends nothing while off air, see `--tally-saving stop`.
+    off_air: AtomicBool,
+    connections: AtomicU64,
+    last_sent: Mutex<Option<Instant>>,
+    /// Latest report published by the NDI thread.
+    last_report: Mutex<Report>,
+    audio_levels: Mutex<Option<(Instant, AudioLevels)>>,
+}
+
+/// Levels below this are reported as silence, in dBFS.
+pub const MIN_LEVEL_DB: f32 = -96.0;
+/// Audio levels are no longer reported once no audio has been sent for this long.
+const AUDIO_LEVELS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Metered levels of the audio being sent, in dBFS, one entry per channel.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AudioLevels {
+    pub peak_db: Vec<f32>,
+    pub rms_db: Vec<f32>,
+}
+
+impl AudioLevels {
+    /// Loudest peak and RMS level over all channels.
+    pub fn max(&self) -> (f32, f32) {
+        let max = |levels: &[f32]| levels.iter().copied().fold(MIN_LEVEL_DB, f32::max);
+        (max(&self.peak_db), max(&self.rms_db))
+    }
+}
+
+impl Stats {
+    pub fn frame_captured(&self) {
+        self.captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_corrupted(&self) {
+        self.corrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the cover or audio of an audio-only source going out, which keeps the share
+    /// counting as streaming as frames do.
+    pub fn audio_only_sent(&self) {
+        *self.last_sent.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Records a frame sent once more, which keeps the share counting as streaming.
+    pub fn frame_repeated(&self) {
+        self.repeated.fetch_add(1, Ordering::Relaxed);
+        *self.last_sent.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Records a frame of `bytes` and `pixels` handed to NDI, `latency` being the time since it
+    /// was captured.
+    pub fn frame_sent(&self, latency: Duration, bytes: u64, pixels: u64) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.sent_pixels.fetch_add(pixels, Ordering::Relaxed);
+        self.latency_total_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        *self.last_sent.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Time since a frame was last handed to NDI, `None` before the first one.
+    pub fn last_sent_age(&self) -> Option<Duration> {
+        self.last_sent.lock().unwrap().map(|t| t.elapsed())
+    }
+
+    pub fn set_connections(&self, connections: u32) {
+        self.connections
+            .store(connections as u64, Ordering::Relaxed);
+    }
+
+    /// Number of NDI receivers connected, as of the last report.
+    pub fn connections(&self) -> u32 {
+        self.connections.load(Ordering::Relaxed) as u32
+    }
+
+    pub fn frame_queued(&self, bytes: u64) {
+        self.queued_bytes.fetch_add(bytes, Ordering::Relaxed);
+        let depth = self.queued_frames.fetch_add(1, Ordering::Relaxed) + 1;
+        self.queue_high_watermark
+            .fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn frame_dequeued(&self, bytes: u64) {
+        self.queued_bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.queued_frames.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records whether the queue was found at its memory budget when a frame arrived.
+    pub fn set_queue_full(&self, full: bool) {
+        let mut since = self.queue_full_since.lock().unwrap();
+        match (full, *since) {
+            (true, None) => *since = Some(Instant::now()),
+            (false, Some(start)) => {
+                self.queue_full_us
+                    .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                *since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Total time the queue spent at its memory budget, including the current stretch.
+    fn queue_full_time(&self) -> Duration {
+        let current = self
+            .queue_full_since
+            .lock()
+            .unwrap()
+            .map_or(Duration::ZERO, |start| start.elapsed());
+        Duration::from_micros(self.queue_full_us.load(Ordering::Relaxed)) + current
+    }
+
+    /// Memory currently held by frames waiting to be sent.
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_sending(&self, sending: bool) {
+        self.sending.store(sending, Ordering::Relaxed);
+    }
+
+    pub fn is_sending(&self) -> bool {
+        self.sending.load(Ordering::Relaxed)
+    }
+
+    pub fn set_off_air(&self, off_air: bool) {
+        self.off_air.store(off_air, Ordering::Relaxed);
+    }
+
+    pub fn is_off_air(&self) -> bool {
+        self.off_air.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            captured: self.captured.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            corrupted: self.corrupted.load(Ordering::Relaxed),
+            repeated: self.repeated.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            sent_pixels: self.sent_pixels.load(Ordering::Relaxed),
+            latency_total_us: self.latency_total_us.load(Ordering::Relaxed),
+            queued_bytes: self.queued_bytes(),
+            queue_depth: self.queued_frames.load(Ordering::Relaxed),
+            queue_high_watermark: self.queue_high_watermark.load(Ordering::Relaxed),
+            queue_full_us: self.queue_full_time().as_micros() as u64,
+        }
+    }
+
+    pub fn set_last_report(&self, report: Report) {
+        *self.last_report.lock().unwrap() = report;
+    }
+
+    pub fn last_report(&self) -> Report {
+        *self.last_report.lock().unwrap()
+    }
+
+    pub fn set_audio_levels(&self, levels: AudioLevels) {
+        *self.audio_levels.lock().unwrap() = Some((Instant::now(), levels));
+    }
+
+    /// Current audio levels, `None` if no audio is flowing.
+    pub fn audio_levels(&self) -> Option<AudioLevels> {
+        match &*self.audio_levels.lock().unwrap() {
+            Some((updated, levels)) if updated.elapsed() < AUDIO_LEVELS_TIMEOUT => {
+                Some(levels.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Snapshot {
+    pub captured: u64,
+    pub dropped: u64,
+    pub corrupted: u64,
+    pub repeated: u64,
+    pub sent: u64,
+    /// Uncompressed frame data handed to NDI.
+    pub sent_bytes: u64,
+    /// Pixels of the frames handed to NDI, whose formats differ in bytes per pixel.
+    pub sent_pixels: u64,
+    pub latency_total_us: u64,
+    pub queued_bytes: u64,
+    /// Frames waiting in the queue.
+    pub queue_depth: u64,
+    /// Most frames ever waiting in the queue at once.
+    pub queue_high_watermark: u64,
+    /// Time the queue spent at its memory budget.
+    pub queue_full_us: u64,
+}
+
+/// Rates over one reporting interval.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Report {
+    pub capture_fps: f64,
+    pub send_fps: f64,
+    pub dropped: u64,
+    pub dropped_total: u64,
+    pub corrupted: u64,
+    pub repeated: u64,
+    pub latency_ms: f64,
+    pub queued_mb: f64,
+    pub queue_depth: u64,
+    pub queue_high_watermark: u64,
+    /// Share of the interval the queue spent at its memory budget, from 0 to 100.
+    pub queue_full_pct: f64,
+    /// Uncompressed frame data handed to NDI, in Mbit/s.
+    pub send_mbps: f64,
+    /// Rough estimate of the compressed bitrate going out to all connected receivers, in Mbit/s.
+    pub network_mbps: f64,
+    /// Loudest channel of the audio being sent at the time of the report, in dBFS.
+    pub audio_peak_db: f32,
+    pub audio_rms_db: f32,
+}
+
+/// NDI's SpeedHQ codec averages about 125 Mbit/s for 1080p60, roughly one bit per pixel.
+const NDI_BITS_PER_PIXEL: f64 = 1.0;
+
+impl Report {
+    fn between(
+        prev: &Snapshot,
+        cur: &Snapshot,
+        elapsed: Duration,
+        connections: u32,
+        audio: Option<AudioLevels>,
+    ) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let bytes_per_sec = (cur.sent_bytes - prev.sent_bytes) as f64 / secs;
+        // Each receiver gets its own stream.
+        let pixels_per_sec = (cur.sent_pixels - prev.sent_pixels) as f64 / secs;
+        let (audio_peak_db, audio_rms_db) = audio
+            .map(|levels| levels.max())
+            .unwrap_or((MIN_LEVEL_DB, MIN_LEVEL_DB));
+        let sent = cur.sent - prev.sent;
+        let latency_ms = if sent > 0 {
+            (cur.latency_total_us - prev.latency_total_us) as f64 / sent as f64 / 1000.0
+        } else {
+            0.0
+        };
+
+        Self {
+            capture_fps: (cur.captured - prev.captured) as f64 / secs,
+            send_fps: sent as f64 / secs,
+            dropped: cur.dropped - prev.dropped,
+            dropped_total: cur.dropped,
+            corrupted: cur.corrupted - prev.corrupted,
+            repeated: cur.repeated - prev.repeated,
+            latency_ms,
+            queued_mb: cur.queued_bytes as f64 / (1024.0 * 1024.0),
+            queue_depth: cur.queue_depth,
+            queue_high_watermark: cur.queue_high_watermark,
+            queue_full_pct: ((cur.queue_full_us - prev.queue_full_us) as f64 / 1e6 / secs * 100.0)
+                .min(100.0),
+            send_mbps: bytes_per_sec * 8.0 / 1e6,
+            network_mbps: pixels_per_sec * NDI_BITS_PER_PIXEL * connections as f64 / 1e6,
+            audio_peak_db,
+            audio_rms_db,
+        }
+    }
+
+    /// Formats the report as an NDI metadata element.
+    pub fn to_xml(&self) -> String {
+        format!(
+            r#"<ndi_screenshare_stats capture_fps="{:.2}" send_fps="{:.2}" dropped="{}" dropped_total="{}" corrupted="{}" repeated="{}" latency_ms="{:.1}" queued_mb="{:.1}" queue_depth="{}" queue_high_watermark="{}" queue_full_pct="{:.1}" send_mbps="{:.1}" network_mbps="{:.1}" audio_peak_db="{:.1}" audio_rms_db="{:.1}"/>"#,
+            self.capture_fps,
+            self.send_fps,
+            self.dropped,
+            self.dropped_total,
+            self.corrupted,
+            self.repeated,
+            self.latency_ms,
+            self.queued_mb,
+            self.queue_depth,
+            self.queue_high_watermark,
+            self.queue_full_pct,
+            self.send_mbps,
+            self.network_mbps,
+            self.audio_peak_db,
+            self.audio_rms_db
+        )
+    }
+}
+
+/// Produces a [`Report`] once every `interval`.
+pub struct Reporter {
+    interval: Duration,
+    last_time: Instant,
+    last: Snapshot,
+}
+
+impl Reporter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_time: Instant::now(),
+            last: Snapshot::default(),
+        }
+    }
+
+    pub fn poll(&mut self, stats: &Stats) -> Option<Report> {
+        let elapsed = self.last_time.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+
+        let cur = stats.snapshot();
+        let report = Report::between(
+            &self.last,
+            &cur,
+            elapsed,
+            stats.connections(),
+            stats.audio_levels(),
+        );
+        self.last = cur;
+        self.last_time = Instant::now();
+
+        Some(report)
+    }
+}
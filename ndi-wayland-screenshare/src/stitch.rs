@@ -0,0 +1,374 @@
+use crossbeam_channel::{Receiver, Select, Sender, TryRecvError};
+use pipewire::spa::{
+    param::video::{VideoFormat, VideoInfoRaw},
+    utils::{Fraction, Rectangle},
+};
+
+use crate::{frame::OwnedFrame, portal::PortalStream};
+
+/// A monitor captured for `--stitch`, with its latest frame.
+pub struct Tile {
+    /// Position of the monitor on the desktop, in logical coordinates.
+    position: Option<(i32, i32)>,
+    logical_size: Option<(u32, u32)>,
+    frames: Receiver<OwnedFrame>,
+    /// Buffers of replaced frames are handed back to the capture through here.
+    pool: Sender<Vec<u8>>,
+    latest: Option<OwnedFrame>,
+}
+
+impl Tile {
+    pub fn new(stream: &PortalStream, frames: Receiver<OwnedFrame>, pool: Sender<Vec<u8>>) -> Self {
+        Self {
+            position: stream.position,
+            logical_size: stream.logical_size,
+            frames,
+            pool,
+            latest: None,
+        }
+    }
+
+    fn update(&mut self, frame: OwnedFrame) {
+        if let Some(old) = self.latest.replace(frame) {
+            self.pool.try_send(old.data).ok();
+        }
+    }
+}
+
+/// Composes the monitors of a desktop into one frame, placed as they are laid out.
+pub struct Stitcher {
+    tiles: Vec<Tile>,
+}
+
+impl Stitcher {
+    pub fn new(tiles: Vec<Tile>) -> Self {
+        Self { tiles }
+    }
+
+    /// Waits until any monitor has a new frame and composes the latest frames of all of them into
+    /// `data`. Returns `None` once one of the captures has stopped.
+    pub fn next(&mut self, data: Vec<u8>) -> Option<OwnedFrame> {
+        loop {
+            self.receive()?;
+            // Monitors refreshing together are composed once.
+            for tile in &mut self.tiles {
+                if let Some(frame) = tile.frames.try_iter().last() {
+                    tile.update(frame);
+                }
+            }
+            // Nothing is sent until every monitor has delivered, so that the size stays the same.
+            if self.tiles.iter().all(|tile| tile.latest.is_some()) {
+                return Some(self.compose(data));
+            }
+        }
+    }
+
+    /// Waits for the next frame of any monitor.
+    fn receive(&mut self) -> Option<()> {
+        loop {
+            let mut select = Select::new();
+            for tile in &self.tiles {
+                select.recv(&tile.frames);
+            }
+            let index = select.ready();
+            match self.tiles[index].frames.try_recv() {
+                Ok(frame) => {
+                    self.tiles[index].update(frame);
+                    return Some(());
+                }
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+    }
+
+    fn compose(&mut self, mut data: Vec<u8>) -> OwnedFrame {
+        let frames: Vec<&OwnedFrame> = self.tiles.iter().flat_map(|tile| &tile.latest).collect();
+        let monitors: Vec<Monitor> = self
+            .tiles
+            .iter()
+            .zip(&frames)
+            .map(|(tile, frame)| Monitor {
+                position: tile.position,
+                logical_size: tile.logical_size,
+                size: (frame.format.size().width, frame.format.size().height),
+            })
+            .collect();
+        let (width, height, placements) = layout(&monitors);
+
+        data.clear();
+        data.resize(width as usize * height as usize * 4, 0);
+        for (frame, placement) in frames.iter().zip(&placements) {
+            draw(frame, placement, &mut data, width);
+        }
+
+        let mut format = VideoInfoRaw::new();
+        format.set_format(VideoFormat::BGRx);
+        format.set_size(Rectangle { width, height });
+        // The fastest monitor sets the pace.
+        let rate = frames
+            .iter()
+            .map(|frame| frame.frame_rate())
+            .filter(|rate| rate.num > 0 && rate.denom > 0)
+            .max_by(|a, b| (a.num as u64 * b.denom as u64).cmp(&(b.num as u64 * a.denom as u64)))
+            .unwrap_or(Fraction { num: 0, denom: 1 });
+        format.set_framerate(rate);
+
+        OwnedFrame {
+            format,
+            // A still monitor must not make the whole frame look stale.
+            create_time: frames.iter().map(|frame| frame.create_time).max().unwrap(),
+            data,
+            stride: width * 4,
+            cursor: None,
+            queued: None,
+            display_aspect: None,
+        }
+    }
+}
+
+/// What the layout needs to know about a monitor.
+#[derive(Debug)]
+struct Monitor {
+    position: Option<(i32, i32)>,
+    logical_size: Option<(u32, u32)>,
+    /// Size of its frames in pixels.
+    size: (u32, u32),
+}
+
+/// Where a monitor is drawn on the stitched frame, in pixels.
+#[derive(Debug, PartialEq)]
+struct Placement {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Places the monitors as they are laid out on the desktop, returning the size of the stitched
+/// frame and where each of them goes.
+///
+/// Everything is scaled to the monitor with the most pixels per logical pixel, so that mixing
+/// HiDPI and regular monitors loses no detail. Monitors the portal gave no position for are
+/// lined up to the right of the others.
+fn layout(monitors: &[Monitor]) -> (u32, u32, Vec<Placement>) {
+    let logical: Vec<(u32, u32)> = monitors
+        .iter()
+        .map(|monitor| monitor.logical_size.unwrap_or(monitor.size))
+        .collect();
+    let scale = monitors
+        .iter()
+        .zip(&logical)
+        .map(|(monitor, &(width, _))| monitor.size.0 as f64 / width.max(1) as f64)
+        .fold(1.0, f64::max);
+
+    let mut positions: Vec<Option<(i32, i32)>> = monitors.iter().map(|m| m.position).collect();
+    let top = positions
+        .iter()
+        .flatten()
+        .map(|&(_, y)| y)
+        .min()
+        .unwrap_or(0);
+    for i in 0..positions.len() {
+        if positions[i].is_none() {
+            let right = positions
+                .iter()
+                .zip(&logical)
+                .filter_map(|(position, &(width, _))| position.map(|(x, _)| x + width as i32))
+                .max()
+                .unwrap_or(0);
+            positions[i] = Some((right, top));
+        }
+    }
+    let positions: Vec<(i32, i32)> = positions.into_iter().flatten().collect();
+    let left = positions.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let top = positions.iter().map(|&(_, y)| y).min().unwrap_or(0);
+
+    let pixels = |v: i64| (v as f64 * scale).round() as u32;
+    let placements: Vec<Placement> = positions
+        .iter()
+        .zip(&logical)
+        .map(|(&(x, y), &(width, height))| Placement {
+            x: pixels((x - left) as i64),
+            y: pixels((y - top) as i64),
+            width: pixels(width as i64),
+            height: pixels(height as i64),
+        })
+        .collect();
+    let width = placements.iter().map(|p| p.x + p.width).max().unwrap_or(0);
+    let height = placements.iter().map(|p| p.y + p.height).max().unwrap_or(0);
+    (width, height, placements)
+}
+
+/// Scales `frame` into its place on a BGRx frame `width` pixels wide.
+fn draw(frame: &OwnedFrame, placement: &Placement, out: &mut [u8], width: u32) {
+    let size = frame.format.size();
+    if size.width == 0 || size.height == 0 {
+        return;
+    }
+    let format = frame.format.format();
+    let bgr = format == VideoFormat::BGRx || format == VideoFormat::BGRA;
+    let out_stride = width as usize * 4;
+    let row_len = placement.width as usize * 4;
+
+    for y in 0..placement.height {
+        let source_y = (y as u64 * size.height as u64 / placement.height as u64) as usize;
+        let start = source_y * frame.stride as usize;
+        let dst_start = (placement.y + y) as usize * out_stride + placement.x as usize * 4;
+        let Some(dst) = out.get_mut(dst_start..dst_start + row_len) else {
+            break;
+        };
+        // Monitors without scaling are copied as they are.
+        if bgr && placement.width == size.width {
+            if let Some(row) = frame.data.get(start..start + row_len) {
+                dst.copy_from_slice(row);
+            }
+            continue;
+        }
+        for (x, pixel) in dst.chunks_exact_mut(4).enumerate() {
+            let source_x = (x as u64 * size.width as u64 / placement.width as u64) as usize;
+            let offset = start + source_x * 4;
+            let Some(src) = frame.data.get(offset..offset + 4) else {
+                break;
+            };
+            pixel.copy_from_slice(&if bgr {
+                [src[0], src[1], src[2], 255]
+            } else {
+                [src[2], src[1], src[0], 255]
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn monitor(position: Option<(i32, i32)>, logical: (u32, u32), size: (u32, u32)) -> Monitor {
+        Monitor {
+            position,
+            logical_size: Some(logical),
+            size,
+        }
+    }
+
+    fn placement(x: u32, y: u32, width: u32, height: u32) -> Placement {
+        Placement {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn scales_up_to_the_sharpest_monitor() {
+        let (width, height, placements) = layout(&[
+            monitor(Some((0, 0)), (1920, 1080), (3840, 2160)),
+            monitor(Some((1920, 0)), (1920, 1080), (1920, 1080)),
+        ]);
+        assert_eq!((width, height), (7680, 2160));
+        assert_eq!(
+            placements,
+            [placement(0, 0, 3840, 2160), placement(3840, 0, 3840, 2160)]
+        );
+    }
+
+    #[test]
+    fn lines_up_monitors_without_position_on_the_right() {
+        let (width, height, placements) = layout(&[
+            monitor(Some((0, 100)), (1920, 1080), (1920, 1080)),
+            Monitor {
+                position: None,
+                logical_size: None,
+                size: (1280, 720),
+            },
+        ]);
+        assert_eq!((width, height), (3200, 1080));
+        assert_eq!(
+            placements,
+            [placement(0, 0, 1920, 1080), placement(1920, 0, 1280, 720)]
+        );
+    }
+
+    #[test]
+    fn keeps_gaps_and_negative_positions() {
+        let (width, height, placements) = layout(&[
+            monitor(Some((-100, 0)), (100, 100), (100, 100)),
+            monitor(Some((100, 50)), (100, 100), (100, 100)),
+        ]);
+        assert_eq!((width, height), (300, 150));
+        assert_eq!(
+            placements,
+            [placement(0, 0, 100, 100), placement(200, 50, 100, 100)]
+        );
+    }
+
+    fn frame(format: VideoFormat, pixels: &[[u8; 4]], rate: u32, age: Duration) -> OwnedFrame {
+        let mut info = VideoInfoRaw::new();
+        info.set_format(format);
+        info.set_size(Rectangle {
+            width: pixels.len() as u32,
+            height: 1,
+        });
+        info.set_framerate(Fraction {
+            num: rate,
+            denom: 1,
+        });
+        OwnedFrame {
+            format: info,
+            create_time: Instant::now() - age,
+            data: pixels.concat(),
+            stride: pixels.len() as u32 * 4,
+            cursor: None,
+            queued: None,
+            display_aspect: None,
+        }
+    }
+
+    fn tile(position: (i32, i32), frame: OwnedFrame) -> Tile {
+        let stream = PortalStream {
+            node_id: 0,
+            source_type: None,
+            logical_size: None,
+            position: Some(position),
+        };
+        let (_, frames) = crossbeam_channel::unbounded();
+        let (pool, _) = crossbeam_channel::unbounded();
+        let mut tile = Tile::new(&stream, frames, pool);
+        tile.latest = Some(frame);
+        tile
+    }
+
+    #[test]
+    fn composes_latest_frames_in_place() {
+        let newest = frame(VideoFormat::RGBx, &[[10, 20, 30, 0]], 60, Duration::ZERO);
+        let create_time = newest.create_time;
+        let mut stitcher = Stitcher::new(vec![
+            tile(
+                (0, 0),
+                frame(
+                    VideoFormat::BGRx,
+                    &[[1, 2, 3, 0], [4, 5, 6, 0]],
+                    30,
+                    Duration::from_secs(1),
+                ),
+            ),
+            tile((3, 0), newest),
+        ]);
+
+        let frame = stitcher.compose(Vec::new());
+        assert_eq!(frame.format.format(), VideoFormat::BGRx);
+        assert_eq!(frame.format.size().width, 4);
+        assert_eq!(frame.format.size().height, 1);
+        assert_eq!(frame.format.framerate().num, 60);
+        assert_eq!(frame.create_time, create_time);
+        // The gap between the monitors stays black, other formats are swapped to BGRx.
+        assert_eq!(
+            frame.data,
+            [1, 2, 3, 0, 4, 5, 6, 0, 0, 0, 0, 0, 30, 20, 10, 255]
+        );
+    }
+}
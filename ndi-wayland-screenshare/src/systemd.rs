@@ -0,0 +1,31 @@
+use std::os::fd::{FromRawFd, OwnedFd};
+
+/// First file descriptor passed by the service manager, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes the sockets passed through socket activation, as `sd_listen_fds` does.
+///
+/// The environment is cleared so that child processes do not pick them up too, which is only
+/// sound while the process has a single thread.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let count = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    // The variables are inherited by children, make sure they were meant for us.
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let count = count
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        })
+        .collect()
+}
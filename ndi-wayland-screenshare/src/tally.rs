@@ -0,0 +1,80 @@
+use std::time::Instant;
+
+use clap::ValueEnum;
+
+use crate::pacing::FramePacer;
+
+/// What a source does while it is on neither program nor preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TallySaving {
+    /// Always send at full quality.
+    Off,
+    /// Send at half resolution and a few frames per second.
+    Reduce,
+    /// Send nothing.
+    Stop,
+}
+
+/// Frame rate of a source reduced for being off air.
+const REDUCED_FPS: u32 = 5;
+/// How much the resolution of a source is divided by while it is off air.
+pub const REDUCED_SCALE: u32 = 2;
+
+/// How a frame should be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Full,
+    Reduced,
+    Skip,
+}
+
+/// Lowers the quality of a source while it is off air, going back to full quality as soon as it
+/// is put on preview.
+pub struct TallyGate {
+    saving: TallySaving,
+    pacer: FramePacer,
+    on_air: bool,
+}
+
+impl TallyGate {
+    pub fn new(saving: TallySaving) -> Self {
+        Self {
+            saving,
+            pacer: FramePacer::new(Some(REDUCED_FPS)),
+            on_air: true,
+        }
+    }
+
+    pub fn saving(&self) -> TallySaving {
+        self.saving
+    }
+
+    /// Whether nothing is sent as the source is off air.
+    pub fn is_stopped(&self) -> bool {
+        self.saving == TallySaving::Stop && !self.on_air
+    }
+
+    /// Decides how a frame arriving at `now` is sent, given the current tally of its source.
+    pub fn check(&mut self, name: &str, tally: ndi::Tally, now: Instant) -> Quality {
+        if self.saving == TallySaving::Off {
+            return Quality::Full;
+        }
+
+        let on_air = tally.on_program || tally.on_preview;
+        if on_air != self.on_air {
+            self.on_air = on_air;
+            if on_air {
+                println!("{} is on air, sending at full quality", name);
+            } else {
+                println!("{} is off air, saving bandwidth", name);
+                self.pacer = FramePacer::new(Some(REDUCED_FPS));
+            }
+        }
+
+        match self.saving {
+            _ if on_air => Quality::Full,
+            TallySaving::Reduce if self.pacer.ready(now) => Quality::Reduced,
+            _ => Quality::Skip,
+        }
+    }
+}
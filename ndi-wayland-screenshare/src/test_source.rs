@@ -0,0 +1,102 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use crossbeam_channel::Receiver;
+use pipewire::spa::{
+    param::video::{VideoFormat, VideoInfoRaw},
+    utils::{Fraction, Rectangle},
+};
+
+use crate::{frame::OwnedFrame, queue::FrameQueue, stats::Stats};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+const FRAME_RATE: u32 = 30;
+
+/// 75% SMPTE color bars, as (r, g, b).
+const BARS: [(u8, u8, u8); 7] = [
+    (191, 191, 191),
+    (191, 191, 0),
+    (0, 191, 191),
+    (0, 191, 0),
+    (191, 0, 191),
+    (191, 0, 0),
+    (0, 0, 191),
+];
+
+/// Generates color bars above a moving gradient instead of capturing anything, so that the
+/// sending side can be worked on without a Wayland session.
+pub fn test_source_loop(
+    queue: FrameQueue,
+    pool: Receiver<Vec<u8>>,
+    stats: Arc<Stats>,
+) -> Result<()> {
+    let mut format = VideoInfoRaw::new();
+    format.set_format(VideoFormat::BGRx);
+    format.set_size(Rectangle {
+        width: WIDTH,
+        height: HEIGHT,
+    });
+    format.set_framerate(Fraction {
+        num: FRAME_RATE,
+        denom: 1,
+    });
+
+    let stride = WIDTH as usize * 4;
+    let bars_height = HEIGHT as usize * 2 / 3;
+    let bars = render_bars(stride, bars_height);
+
+    let interval = Duration::from_secs(1) / FRAME_RATE;
+    let mut next = Instant::now();
+    for frame_no in 0u64.. {
+        let mut data = pool.try_recv().unwrap_or_default();
+        data.clear();
+        data.extend_from_slice(&bars);
+        data.resize(stride * HEIGHT as usize, 0);
+        render_gradient(&mut data[bars.len()..], stride, frame_no);
+
+        stats.frame_captured();
+        queue.push(OwnedFrame {
+            format,
+            create_time: Instant::now(),
+            data,
+            stride: stride as u32,
+            cursor: None,
+            queued: None,
+            display_aspect: None,
+        });
+
+        next += interval;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            // Running late, do not try to catch up.
+            None => next = Instant::now(),
+        }
+    }
+
+    Ok(())
+}
+
+fn render_bars(stride: usize, height: usize) -> Vec<u8> {
+    let mut row = Vec::with_capacity(stride);
+    for x in 0..WIDTH as usize {
+        let (r, g, b) = BARS[x * BARS.len() / WIDTH as usize];
+        row.extend_from_slice(&[b, g, r, 255]);
+    }
+    row.repeat(height)
+}
+
+/// Fills `data` with a horizontal grey ramp that scrolls one pixel per frame.
+fn render_gradient(data: &mut [u8], stride: usize, frame_no: u64) {
+    let mut row = Vec::with_capacity(stride);
+    for x in 0..WIDTH as u64 {
+        let v = ((x + frame_no) % WIDTH as u64 * 255 / WIDTH as u64) as u8;
+        row.extend_from_slice(&[v, v, v, 255]);
+    }
+    for line in data.chunks_exact_mut(stride) {
+        line.copy_from_slice(&row);
+    }
+}
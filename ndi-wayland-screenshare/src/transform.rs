@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+
+/// Clockwise rotation applied to the main source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Rotation {
+    #[default]
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Cw90,
+    #[value(name = "180")]
+    Cw180,
+    #[value(name = "270")]
+    Cw270,
+}
+
+/// A size written as `WxH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Size must be WxH: {}", s);
+        let (width, height) = s.split_once('x').ok_or_else(error)?;
+        let number = |v: &str| v.trim().parse::<u32>().with_context(error);
+        let size = Self {
+            width: number(width)?,
+            height: number(height)?,
+        };
+        if size.width == 0 || size.height == 0 {
+            return Err(error());
+        }
+        Ok(size)
+    }
+}
+
+/// A rectangle of the captured frame, written as `X,Y,WxH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub size: Size,
+}
+
+impl FromStr for Rect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || anyhow!("Region must be X,Y,WxH: {}", s);
+        let (x, rest) = s.split_once(',').ok_or_else(error)?;
+        let (y, size) = rest.split_once(',').ok_or_else(error)?;
+        let number = |v: &str| v.trim().parse::<u32>().with_context(error);
+        Ok(Self {
+            x: number(x)?,
+            y: number(y)?,
+            size: size.parse().with_context(error)?,
+        })
+    }
+}
+
+/// Reshapes the frames of the main source: crops a region, rotates it, then fits it on a
+/// canvas of a fixed size. Together they turn a landscape desktop or a rotated monitor into a
+/// portrait frame for vertical displays.
+#[derive(Debug, Clone, Default)]
+pub struct Transform {
+    pub region: Option<Rect>,
+    pub rotation: Rotation,
+    pub canvas: Option<Size>,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.region.is_none() && self.rotation == Rotation::None && self.canvas.is_none()
+    }
+
+    /// Transforms a packed 32-bit frame, returning the new pixels and their size.
+    pub fn apply(&self, data: &[u8], width: u32, height: u32, stride: usize) -> (Vec<u8>, Size) {
+        // Clip the region to the frame, the whole frame if there is nothing left.
+        let (cx, cy, cw, ch) = match self.region {
+            Some(r) if r.x < width && r.y < height => (
+                r.x,
+                r.y,
+                r.size.width.min(width - r.x),
+                r.size.height.min(height - r.y),
+            ),
+            _ => (0, 0, width, height),
+        };
+        let (rw, rh) = match self.rotation {
+            Rotation::Cw90 | Rotation::Cw270 => (ch, cw),
+            Rotation::None | Rotation::Cw180 => (cw, ch),
+        };
+        let canvas = self.canvas.unwrap_or(Size {
+            width: rw,
+            height: rh,
+        });
+
+        // Opaque black around the content.
+        let mut out = [0, 0, 0, 255].repeat(canvas.width as usize * canvas.height as usize);
+        if rw == 0 || rh == 0 {
+            return (out, canvas);
+        }
+
+        let scale = (canvas.width as f64 / rw as f64).min(canvas.height as f64 / rh as f64);
+        let w = ((rw as f64 * scale) as u32).clamp(1, canvas.width);
+        let h = ((rh as f64 * scale) as u32).clamp(1, canvas.height);
+        let (x0, y0) = ((canvas.width - w) / 2, (canvas.height - h) / 2);
+
+        for y in 0..h {
+            let v = (y as u64 * rh as u64 / h as u64) as u32;
+            for x in 0..w {
+                let u = (x as u64 * rw as u64 / w as u64) as u32;
+                // Where the pixel at (u, v) of the rotated content comes from in the region.
+                let (sx, sy) = match self.rotation {
+                    Rotation::None => (u, v),
+                    Rotation::Cw90 => (v, ch - 1 - u),
+                    Rotation::Cw180 => (cw - 1 - u, ch - 1 - v),
+                    Rotation::Cw270 => (cw - 1 - v, u),
+                };
+
+                let src = (cy + sy) as usize * stride + (cx + sx) as usize * 4;
+                let dst = ((y0 + y) as usize * canvas.width as usize + (x0 + x) as usize) * 4;
+                if let Some(pixel) = data.get(src..src + 4) {
+                    out[dst..dst + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        (out, canvas)
+    }
+}
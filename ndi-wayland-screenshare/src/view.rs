@@ -0,0 +1,152 @@
+use std::{
+    num::NonZeroU32,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use ndi::{Frame, NdiLib, Source};
+use softbuffer::{SoftBufferError, Surface};
+use winit::{
+    dpi::PhysicalSize,
+    event::{Event, WindowEvent},
+    event_loop::{EventLoopBuilder, EventLoopProxy},
+    window::{Window, WindowBuilder},
+};
+
+/// How long a capture waits for a frame before checking whether the window is still open.
+const CAPTURE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Looks for the source called `name`, with or without the machine part, for up to `wait`.
+pub fn find_source(ndi: &NdiLib, name: &str, wait: Duration) -> Result<Source> {
+    let finder = ndi.create_finder(true, None)?;
+    let matches = |source: &Source| {
+        source.name == name
+            || source
+                .machine_and_name()
+                .is_some_and(|(_, source_name)| source_name == name)
+    };
+
+    let started = Instant::now();
+    loop {
+        if let Some(source) = finder.current_sources().into_iter().find(matches) {
+            return Ok(source);
+        }
+        let Some(left) = wait.checked_sub(started.elapsed()) else {
+            return Err(anyhow!(
+                "No NDI source named \"{}\" was found, see list-sources",
+                name
+            ));
+        };
+        finder.wait_for_sources(left);
+    }
+}
+
+/// Shows what `source` sends in a window until it is closed.
+pub fn run(source: Source) -> Result<()> {
+    let event_loop = EventLoopBuilder::<Frame<Vec<u8>>>::with_user_event().build()?;
+    let window = Rc::new(
+        WindowBuilder::new()
+            .with_title(&source.name)
+            .build(&event_loop)?,
+    );
+    let context = softbuffer::Context::new(window.clone()).map_err(|e| anyhow!("{}", e))?;
+    let mut surface = Surface::new(&context, window.clone()).map_err(|e| anyhow!("{}", e))?;
+
+    let proxy = event_loop.create_proxy();
+    std::thread::spawn(move || {
+        if let Err(e) = receive(&source, proxy) {
+            eprintln!("Error: {}", e);
+        }
+    });
+
+    let mut latest: Option<Frame<Vec<u8>>> = None;
+    event_loop.run(move |event, target| match event {
+        Event::UserEvent(frame) => {
+            // The window starts out at the size of the source.
+            if latest.is_none() {
+                let _ = window.request_inner_size(PhysicalSize::new(frame.width, frame.height));
+            }
+            latest = Some(frame);
+            window.request_redraw();
+        }
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => target.exit(),
+            WindowEvent::RedrawRequested => {
+                if let Err(e) = draw(&mut surface, window.inner_size(), latest.as_ref()) {
+                    eprintln!("Cannot draw the frame: {}", e);
+                    target.exit();
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    })?;
+    Ok(())
+}
+
+/// Hands every frame received from `source` to the window, until it is closed.
+fn receive(source: &Source, proxy: EventLoopProxy<Frame<Vec<u8>>>) -> Result<()> {
+    let ndi = NdiLib::new()?;
+    let receiver = ndi.create_receiver(source, None)?;
+    println!("Connected to {}", source.name);
+    loop {
+        if let Some(frame) = receiver.capture_video(CAPTURE_TIMEOUT) {
+            if proxy.send_event(frame).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn draw(
+    surface: &mut Surface<Rc<Window>, Rc<Window>>,
+    size: PhysicalSize<u32>,
+    frame: Option<&Frame<Vec<u8>>>,
+) -> Result<(), SoftBufferError> {
+    let (Some(width), Some(height)) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+    else {
+        return Ok(());
+    };
+    surface.resize(width, height)?;
+    let mut buffer = surface.buffer_mut()?;
+    buffer.fill(0);
+    if let Some(frame) = frame {
+        fit(frame, &mut buffer, size.width, size.height);
+    }
+    buffer.present()
+}
+
+/// Scales `frame` into a window of `width` by `height` pixels as `0RGB` values, keeping its
+/// aspect ratio and leaving the rest black.
+fn fit(frame: &Frame<Vec<u8>>, pixels: &mut [u32], width: u32, height: u32) {
+    if frame.width == 0 || frame.height == 0 {
+        return;
+    }
+    let aspect = frame
+        .picture_aspect_ratio
+        .unwrap_or(frame.width as f32 / frame.height as f32);
+    let (fit_width, fit_height) = if width as f32 / height as f32 > aspect {
+        ((height as f32 * aspect) as u32, height)
+    } else {
+        (width, (width as f32 / aspect) as u32)
+    };
+    let (x0, y0) = ((width - fit_width) / 2, (height - fit_height) / 2);
+
+    // Receivers are asked for BGRA or BGRX, which read as `0RGB` once the alpha is masked.
+    for y in 0..fit_height {
+        let source_y = (y as u64 * frame.height as u64 / fit_height as u64) as usize;
+        let row = source_y * frame.stride_in_bytes as usize;
+        let start = ((y0 + y) * width + x0) as usize;
+        for (x, pixel) in pixels[start..start + fit_width as usize]
+            .iter_mut()
+            .enumerate()
+        {
+            let source_x = (x as u64 * frame.width as u64 / fit_width as u64) as usize;
+            let offset = row + source_x * 4;
+            if let Some(bgra) = frame.data.get(offset..offset + 4) {
+                *pixel = u32::from_le_bytes([bgra[0], bgra[1], bgra[2], 0]);
+            }
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use std::{ffi::CStr, path::PathBuf, ptr::null};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    ffi::CStr,
+    path::PathBuf,
+    ptr::{null, null_mut},
+    time::Duration,
+};
 
 use anyhow::Result;
 use ndi_sys as ffi;
@@ -49,62 +56,456 @@ impl NdiLib {
         clock_video: bool,
         clock_audio: bool,
     ) -> Result<Sender> {
-        let name = name.map(|s| std::ffi::CString::new(s).unwrap());
-        let group = group.map(|s| std::ffi::CString::new(s).unwrap());
-
-        let param = ffi::NDIlib_send_create_t {
-            p_ndi_name: name.map(|s| s.as_ptr()).unwrap_or(null()),
-            p_groups: group.map(|s| s.as_ptr()).unwrap_or(null()),
+        let params = SendParams {
+            name: name.map(|s| std::ffi::CString::new(s).unwrap()),
+            group: group.map(|s| std::ffi::CString::new(s).unwrap()),
             clock_video,
             clock_audio,
         };
-
-        let sender = unsafe { (*self.lib_ptr).__bindgen_anon_9.send_create.unwrap()(&param) };
-        if sender.is_null() {
-            return Err(anyhow::anyhow!("Failed to create sender"));
-        }
+        let sender = params.create(self.lib_ptr)?;
 
         Ok(Sender {
             lib_ptr: self.lib_ptr,
             sender_ptr: sender,
+            in_flight: None,
+            params,
+            failed: Cell::new(false),
+            received: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    /// Starts looking for sources on the network, in `groups` or the default group.
+    pub fn create_finder(&self, show_local_sources: bool, groups: Option<&str>) -> Result<Finder> {
+        let groups = groups.map(|s| std::ffi::CString::new(s).unwrap());
+
+        let param = ffi::NDIlib_find_create_t {
+            show_local_sources,
+            p_groups: groups.as_ref().map(|s| s.as_ptr()).unwrap_or(null()),
+            p_extra_ips: null(),
+        };
+
+        let finder = unsafe { (*self.lib_ptr).__bindgen_anon_6.find_create_v2.unwrap()(&param) };
+        if finder.is_null() {
+            return Err(anyhow::anyhow!("Failed to create finder"));
+        }
+
+        Ok(Finder {
+            lib_ptr: self.lib_ptr,
+            finder_ptr: finder,
+        })
+    }
+
+    /// Connects to `source`, receiving video as BGRA, or BGRX when there is no alpha.
+    pub fn create_receiver(&self, source: &Source, name: Option<&str>) -> Result<Receiver> {
+        let source_name = std::ffi::CString::new(source.name.as_str())?;
+        let url_address = source
+            .url_address
+            .as_deref()
+            .map(std::ffi::CString::new)
+            .transpose()?;
+        let name = name.map(|s| std::ffi::CString::new(s).unwrap());
+
+        let mut source_to_connect_to: ffi::NDIlib_source_t = unsafe { std::mem::zeroed() };
+        source_to_connect_to.p_ndi_name = source_name.as_ptr();
+        source_to_connect_to.__bindgen_anon_1.p_url_address =
+            url_address.as_ref().map(|s| s.as_ptr()).unwrap_or(null());
+        let param = ffi::NDIlib_recv_create_v3_t {
+            source_to_connect_to,
+            color_format: ffi::NDIlib_recv_color_format_e_NDIlib_recv_color_format_BGRX_BGRA,
+            bandwidth: ffi::NDIlib_recv_bandwidth_e_NDIlib_recv_bandwidth_highest,
+            allow_video_fields: false,
+            p_ndi_recv_name: name.as_ref().map(|s| s.as_ptr()).unwrap_or(null()),
+        };
+
+        let receiver = unsafe { (*self.lib_ptr).__bindgen_anon_85.recv_create_v3.unwrap()(&param) };
+        if receiver.is_null() {
+            return Err(anyhow::anyhow!("Failed to create receiver"));
+        }
+
+        Ok(Receiver {
+            lib_ptr: self.lib_ptr,
+            receiver_ptr: receiver,
+        })
+    }
+}
+
+/// A source seen on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// Full name of the source, in the form `MACHINE (NAME)`.
+    pub name: String,
+    pub url_address: Option<String>,
+}
+
+impl Source {
+    /// Splits the full name into the machine and the name of the source on it.
+    pub fn machine_and_name(&self) -> Option<(&str, &str)> {
+        let (machine, name) = self.name.split_once(" (")?;
+        Some((machine, name.strip_suffix(')')?))
+    }
+}
+
+/// Discovers sources in the background for as long as it is alive.
+pub struct Finder {
+    lib_ptr: *const ffi::NDIlib_v5,
+    finder_ptr: ffi::NDIlib_find_instance_t,
+}
+
+impl Finder {
+    /// Waits until the list of sources changes, returning `false` on timeout.
+    pub fn wait_for_sources(&self, timeout: Duration) -> bool {
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_43
+                .find_wait_for_sources
+                .unwrap()(self.finder_ptr, timeout.as_millis() as u32)
+        }
+    }
+
+    /// The sources known right now.
+    pub fn current_sources(&self) -> Vec<Source> {
+        let mut count = 0;
+        let sources = unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_44
+                .find_get_current_sources
+                .unwrap()(self.finder_ptr, &mut count)
+        };
+        if sources.is_null() {
+            return Vec::new();
+        }
+
+        let string = |ptr: *const std::os::raw::c_char| {
+            (!ptr.is_null()).then(|| unsafe { CStr::from_ptr(ptr).to_string_lossy().into_owned() })
+        };
+        // Only valid until the next call, so everything is copied out.
+        unsafe { std::slice::from_raw_parts(sources, count as usize) }
+            .iter()
+            .map(|source| Source {
+                name: string(source.p_ndi_name).unwrap_or_default(),
+                url_address: string(unsafe { source.__bindgen_anon_1.p_url_address }),
+            })
+            .collect()
+    }
+}
+
+impl Drop for Finder {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.lib_ptr).__bindgen_anon_7.find_destroy.unwrap()(self.finder_ptr);
+        }
+    }
+}
+
+/// Receives from one source on the network for as long as it is alive.
+pub struct Receiver {
+    lib_ptr: *const ffi::NDIlib_v5,
+    receiver_ptr: ffi::NDIlib_recv_instance_t,
+}
+
+impl Receiver {
+    /// Waits up to `timeout` for the next video frame and copies it out. Audio and metadata
+    /// are not asked for.
+    pub fn capture_video(&self, timeout: Duration) -> Option<Frame<Vec<u8>>> {
+        let mut video: ffi::NDIlib_video_frame_v2_t = unsafe { std::mem::zeroed() };
+        let frame_type = unsafe {
+            (*self.lib_ptr).__bindgen_anon_50.recv_capture_v2.unwrap()(
+                self.receiver_ptr,
+                &mut video,
+                null_mut(),
+                null_mut(),
+                timeout.as_millis() as u32,
+            )
+        };
+        if frame_type != ffi::NDIlib_frame_type_e_NDIlib_frame_type_video {
+            return None;
+        }
+
+        let stride = unsafe { video.__bindgen_anon_1.line_stride_in_bytes } as u32;
+        let len = stride as usize * video.yres as usize;
+        let data = (!video.p_data.is_null())
+            .then(|| unsafe { std::slice::from_raw_parts(video.p_data, len) }.to_vec());
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_48
+                .recv_free_video_v2
+                .unwrap()(self.receiver_ptr, &video);
+        }
+
+        Some(Frame {
+            width: video.xres as u32,
+            height: video.yres as u32,
+            format: VideoFormat::from_fourcc(video.FourCC)?,
+            data: data?,
+            stride_in_bytes: stride,
+            picture_aspect_ratio: (video.picture_aspect_ratio > 0.0)
+                .then_some(video.picture_aspect_ratio),
+            frame_rate: (video.frame_rate_N > 0 && video.frame_rate_D > 0)
+                .then_some((video.frame_rate_N as u32, video.frame_rate_D as u32)),
         })
     }
 }
 
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.lib_ptr).__bindgen_anon_24.recv_destroy.unwrap()(self.receiver_ptr);
+        }
+    }
+}
+
+/// What a sender was created with, kept to create it again.
+struct SendParams {
+    name: Option<std::ffi::CString>,
+    group: Option<std::ffi::CString>,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+impl SendParams {
+    fn create(&self, lib_ptr: *const ffi::NDIlib_v5) -> Result<ffi::NDIlib_send_instance_t> {
+        let param = ffi::NDIlib_send_create_t {
+            p_ndi_name: self.name.as_ref().map(|s| s.as_ptr()).unwrap_or(null()),
+            p_groups: self.group.as_ref().map(|s| s.as_ptr()).unwrap_or(null()),
+            clock_video: self.clock_video,
+            clock_audio: self.clock_audio,
+        };
+
+        let sender = unsafe { (*lib_ptr).__bindgen_anon_9.send_create.unwrap()(&param) };
+        if sender.is_null() {
+            return Err(anyhow::anyhow!("Failed to create sender"));
+        }
+        Ok(sender)
+    }
+}
+
 pub struct Sender {
     lib_ptr: *const ffi::NDIlib_v5,
     sender_ptr: ffi::NDIlib_send_instance_t,
+    /// Buffer of the last asynchronously sent frame, still owned by the SDK.
+    in_flight: Option<Vec<u8>>,
+    params: SendParams,
+    /// Set once the SDK reported an error for this instance.
+    failed: Cell<bool>,
+    /// Metadata frames taken from the SDK while checking for errors, not yet received.
+    received: RefCell<VecDeque<String>>,
 }
 
+/// Metadata frames kept for `receive_metadata`, the oldest are dropped beyond this.
+const MAX_RECEIVED_METADATA: usize = 16;
+
 impl Sender {
-    pub fn send(&self, frame: Frame) {
-        let mut frame_v2: ffi::NDIlib_video_frame_v2_t = unsafe { std::mem::zeroed() };
-        frame_v2.xres = frame.width as i32;
-        frame_v2.yres = frame.height as i32;
-        frame_v2.FourCC = frame.format.to_fourcc();
-        frame_v2.p_data = frame.data.as_mut_ptr();
-        frame_v2.__bindgen_anon_1.line_stride_in_bytes = frame.stride_in_bytes as i32;
-        frame_v2.timecode = ffi::NDIlib_send_timecode_synthesize;
+    /// Whether the SDK still considers the sender usable.
+    ///
+    /// The send calls return nothing, so the only failure the SDK reports is `send_capture`
+    /// returning `NDIlib_frame_type_error`. It is polled after every video frame sent and when
+    /// receiving metadata, so a broken instance shows here from the next frame on.
+    pub fn is_healthy(&self) -> bool {
+        !self.failed.get()
+    }
+
+    /// Replaces the SDK instance with a new one of the same name and groups, for when it stopped
+    /// working. Connection metadata has to be set again.
+    ///
+    /// The new instance is created first, so that the sender is left as it was if that fails.
+    pub fn recreate(&mut self) -> Result<()> {
+        let sender = self.params.create(self.lib_ptr)?;
+        self.flush_async();
+        unsafe {
+            (*self.lib_ptr).__bindgen_anon_10.send_destroy.unwrap()(self.sender_ptr);
+        }
+        self.sender_ptr = sender;
+        self.failed.set(false);
+        self.received.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn send<D: AsMut<[u8]>>(&self, mut frame: Frame<D>) {
+        let frame_v2 = frame.to_ffi();
         unsafe {
             (*self.lib_ptr)
                 .__bindgen_anon_51
                 .send_send_video_v2
                 .unwrap()(self.sender_ptr, &frame_v2);
         }
+        self.check_failure();
     }
 
-    pub fn connections_count(&self) -> u32 {
+    /// Schedules a frame for sending and returns immediately.
+    ///
+    /// The SDK reads from the frame buffer until the next asynchronous send, so the buffer is
+    /// kept alive by the sender until then. The buffer submitted by the previous call is
+    /// released at this point and handed back for reuse.
+    pub fn send_async(&mut self, mut frame: Frame<Vec<u8>>) -> Option<Vec<u8>> {
+        let frame_v2 = frame.to_ffi();
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_52
+                .send_send_video_async_v2
+                .unwrap()(self.sender_ptr, &frame_v2);
+        }
+        self.check_failure();
+        self.in_flight.replace(frame.data)
+    }
+
+    /// Waits for the SDK to finish with the in-flight asynchronous frame and returns its buffer.
+    pub fn flush_async(&mut self) -> Option<Vec<u8>> {
         unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_52
+                .send_send_video_async_v2
+                .unwrap()(self.sender_ptr, null());
+        }
+        self.in_flight.take()
+    }
+
+    /// Sends an XML metadata frame to all connected receivers.
+    pub fn send_metadata(&self, data: &str) {
+        let data = std::ffi::CString::new(data).unwrap();
+
+        let mut frame: ffi::NDIlib_metadata_frame_t = unsafe { std::mem::zeroed() };
+        frame.length = data.as_bytes_with_nul().len() as i32;
+        frame.timecode = ffi::NDIlib_send_timecode_synthesize;
+        frame.p_data = data.as_ptr() as *mut _;
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_14
+                .send_send_metadata
+                .unwrap()(self.sender_ptr, &frame);
+        }
+    }
+
+    /// Replaces the XML metadata every receiver is sent when it connects.
+    pub fn set_connection_metadata(&self, data: &str) {
+        let data = std::ffi::CString::new(data).unwrap();
+
+        let mut frame: ffi::NDIlib_metadata_frame_t = unsafe { std::mem::zeroed() };
+        frame.length = data.as_bytes_with_nul().len() as i32;
+        frame.timecode = ffi::NDIlib_send_timecode_synthesize;
+        frame.p_data = data.as_ptr() as *mut _;
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_19
+                .send_clear_connection_metadata
+                .unwrap()(self.sender_ptr);
+            (*self.lib_ptr)
+                .__bindgen_anon_20
+                .send_add_connection_metadata
+                .unwrap()(self.sender_ptr, &frame);
+        }
+    }
+
+    /// Returns the next metadata frame sent by a connected receiver, waiting up to `timeout`.
+    pub fn receive_metadata(&self, timeout: Duration) -> Option<String> {
+        if let Some(data) = self.received.borrow_mut().pop_front() {
+            return Some(data);
+        }
+        // Frames without text are skipped, they do not mean that nothing is left.
+        while let Some(data) = self.capture(timeout.as_millis() as u32) {
+            if data.is_some() {
+                return data;
+            }
+        }
+        None
+    }
+
+    /// Takes what the SDK has waiting for the sender, so that an error behind it shows. Metadata
+    /// frames are kept for `receive_metadata`.
+    fn check_failure(&self) {
+        while let Some(data) = self.capture(0) {
+            let mut received = self.received.borrow_mut();
+            if received.len() == MAX_RECEIVED_METADATA {
+                received.pop_front();
+            }
+            received.extend(data);
+        }
+    }
+
+    /// Waits up to `timeout` milliseconds for a metadata frame and returns it, with `None` inside
+    /// for one without text. Records an error reported instead.
+    fn capture(&self, timeout: u32) -> Option<Option<String>> {
+        let mut frame: ffi::NDIlib_metadata_frame_t = unsafe { std::mem::zeroed() };
+        let frame_type = unsafe {
+            (*self.lib_ptr).__bindgen_anon_15.send_capture.unwrap()(
+                self.sender_ptr,
+                &mut frame,
+                timeout,
+            )
+        };
+        if frame_type == ffi::NDIlib_frame_type_e_NDIlib_frame_type_error {
+            self.failed.set(true);
+        }
+        if frame_type != ffi::NDIlib_frame_type_e_NDIlib_frame_type_metadata {
+            return None;
+        }
+
+        let data = (!frame.p_data.is_null())
+            .then(|| unsafe { CStr::from_ptr(frame.p_data).to_string_lossy().into_owned() });
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_16
+                .send_free_metadata
+                .unwrap()(self.sender_ptr, &frame);
+        }
+        Some(data)
+    }
+
+    pub fn send_audio(&self, frame: &AudioFrame) {
+        let mut frame_v2: ffi::NDIlib_audio_frame_v2_t = unsafe { std::mem::zeroed() };
+        frame_v2.sample_rate = frame.sample_rate as i32;
+        frame_v2.no_channels = frame.channels as i32;
+        frame_v2.no_samples = frame.samples as i32;
+        frame_v2.timecode = frame
+            .timecode
+            .unwrap_or(ffi::NDIlib_send_timecode_synthesize);
+        // The SDK only reads from the buffer.
+        frame_v2.p_data = frame.data.as_ptr() as *mut f32;
+        frame_v2.channel_stride_in_bytes =
+            (frame.samples as usize * std::mem::size_of::<f32>()) as i32;
+        unsafe {
+            (*self.lib_ptr)
+                .__bindgen_anon_53
+                .send_send_audio_v2
+                .unwrap()(self.sender_ptr, &frame_v2);
+        }
+    }
+
+    /// Whether receivers currently show this source on program or preview.
+    pub fn tally(&self) -> Tally {
+        let mut tally = ffi::NDIlib_tally_t {
+            on_program: false,
+            on_preview: false,
+        };
+        unsafe {
+            (*self.lib_ptr).__bindgen_anon_17.send_get_tally.unwrap()(
+                self.sender_ptr,
+                &mut tally,
+                0,
+            );
+        }
+        Tally {
+            on_program: tally.on_program,
+            on_preview: tally.on_preview,
+        }
+    }
+
+    pub fn connections_count(&self) -> u32 {
+        let connections = unsafe {
             (*self.lib_ptr)
                 .__bindgen_anon_18
                 .send_get_no_connections
-                .unwrap()(self.sender_ptr, 0) as u32
-        }
+                .unwrap()(self.sender_ptr, 0)
+        };
+        connections.max(0) as u32
     }
 }
 
+// The SDK lets senders be used from any thread, as long as calls are not made concurrently.
+unsafe impl Send for Sender {}
+
 impl Drop for Sender {
     fn drop(&mut self) {
+        self.flush_async();
         unsafe {
             (*self.lib_ptr).__bindgen_anon_10.send_destroy.unwrap()(self.sender_ptr);
         }
@@ -117,6 +518,8 @@ pub enum VideoFormat {
     RGBX,
     BGRA,
     BGRX,
+    /// 4:2:2 YUV, two bytes per pixel.
+    UYVY,
 }
 
 impl VideoFormat {
@@ -126,16 +529,70 @@ impl VideoFormat {
             VideoFormat::RGBX => ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_RGBX,
             VideoFormat::BGRA => ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_BGRA,
             VideoFormat::BGRX => ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_BGRX,
+            VideoFormat::UYVY => ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_UYVY,
+        }
+    }
+
+    fn from_fourcc(fourcc: ffi::NDIlib_FourCC_video_type_e) -> Option<Self> {
+        match fourcc {
+            ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_RGBA => Some(VideoFormat::RGBA),
+            ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_RGBX => Some(VideoFormat::RGBX),
+            ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_BGRA => Some(VideoFormat::BGRA),
+            ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_BGRX => Some(VideoFormat::BGRX),
+            ffi::NDIlib_FourCC_video_type_e_NDIlib_FourCC_type_UYVY => Some(VideoFormat::UYVY),
+            _ => None,
         }
     }
 }
 
-pub struct Frame<'a> {
+pub struct Frame<D> {
     pub width: u32,
     pub height: u32,
     pub format: VideoFormat,
-    pub data: &'a mut [u8],
+    pub data: D,
     pub stride_in_bytes: u32,
+    /// Width over height of the picture as displayed, `None` for square pixels.
+    pub picture_aspect_ratio: Option<f32>,
+    /// Frames per second as numerator and denominator, e.g. `(30000, 1001)` for 29.97. The SDK
+    /// synthesizes timecodes from it.
+    pub frame_rate: Option<(u32, u32)>,
+}
+
+impl<D: AsMut<[u8]>> Frame<D> {
+    fn to_ffi(&mut self) -> ffi::NDIlib_video_frame_v2_t {
+        let mut frame_v2: ffi::NDIlib_video_frame_v2_t = unsafe { std::mem::zeroed() };
+        frame_v2.xres = self.width as i32;
+        frame_v2.yres = self.height as i32;
+        frame_v2.FourCC = self.format.to_fourcc();
+        frame_v2.p_data = self.data.as_mut().as_mut_ptr();
+        frame_v2.__bindgen_anon_1.line_stride_in_bytes = self.stride_in_bytes as i32;
+        // Zero tells the SDK that pixels are square.
+        frame_v2.picture_aspect_ratio = self.picture_aspect_ratio.unwrap_or(0.0);
+        if let Some((num, denom)) = self.frame_rate {
+            frame_v2.frame_rate_N = num as i32;
+            frame_v2.frame_rate_D = denom as i32;
+        }
+        frame_v2.timecode = ffi::NDIlib_send_timecode_synthesize;
+        frame_v2
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub on_program: bool,
+    pub on_preview: bool,
+}
+
+/// Planar 32-bit float audio.
+pub struct AudioFrame<'a> {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Number of samples per channel.
+    pub samples: u32,
+    /// Timecode in 100 ns units, synthesized by the SDK when `None`.
+    pub timecode: Option<i64>,
+    /// `channels` planes of `samples` values each, back to back.
+    pub data: &'a [f32],
 }
 
 #[cfg(test)]